@@ -1,41 +1,141 @@
 // KenKen puzzle solver, (c) 2016 Georg Brandl.
 
 use std::fmt;
-use std::iter::repeat;
-use std::cmp::min;
+use std::mem;
+use std::cmp::{min, max};
 
-use {KenKen, Cage, Op};
-use helpers::{Tbl, BitSet, SmallVec};
+use {KenKen, Cage, Op, HouseKind};
+use helpers::{Tbl, BitSet, CandVec, SmallVec};
+use techniques::Techniques;
+
+/// Returns the greatest common divisor of two non-negative numbers (with
+/// gcd(0, n) = n).
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Returns true if `n` is a multiple of `d`, and `n / d` fits into an `i32`
+/// (which excludes `i32::MIN / -1`).
+fn divides(d: i32, n: i32) -> bool {
+    if d == 0 { n == 0 } else { n.checked_rem(d) == Some(0) }
+}
+
+/// The numbers that can be used for a cage, in ascending order.
+///
+/// Candidate vectors can only hold small non-negative numbers, so each number
+/// n is stored as n - offset (see `KenKen::offset`).
+struct Numbers {
+    vals: Vec<i32>,
+    offset: i32,
+}
+
+impl Numbers {
+    /// Returns all numbers of the puzzle.
+    fn of(ken: &KenKen) -> Numbers {
+        Numbers { vals: ken.digits(), offset: ken.offset() }
+    }
+
+    /// Returns the numbers that fulfill the predicate.
+    fn filter<F: Fn(i32) -> bool>(&self, pred: F) -> Numbers {
+        Numbers { vals: self.vals.iter().cloned().filter(|&n| pred(n)).collect(),
+                  offset: self.offset }
+    }
+
+    fn contains(&self, n: i32) -> bool {
+        self.vals.binary_search(&n).is_ok()
+    }
+
+    /// Returns how `n` is stored in a candidate vector.
+    fn enc(&self, n: i32) -> u32 {
+        (n - self.offset) as u32
+    }
+}
 
 /// Holds all candidate sequences for a single cage.
-struct CageCandidates(Vec<SmallVec>);
+struct CageCandidates<V>(Vec<V>);
 
-impl CageCandidates {
+impl<V: CandVec> CageCandidates<V> {
     /// Generates initial candidates.
-    fn from_cage(ken: &KenKen, cage: &Cage) -> CageCandidates {
-        let size = ken.size as u32;
+    fn from_cage(ken: &KenKen, cage: &Cage) -> CageCandidates<V> {
         let ncells = cage.cells.len() as u32;
+        let nums = &Numbers::of(ken);
+        // the extended operations only use the numbers from zero on
+        let nonneg = nums.filter(|n| n >= 0);
         match cage.operation {
-            Op::Add(goal) => CageCandidates(Self::for_add(size, goal, ncells)).reduced(cage),
-            Op::Mul(goal) => CageCandidates(Self::for_mul(size, goal, ncells)).reduced(cage),
-            Op::Sub(goal) => CageCandidates(Self::for_sub(size, goal)),
-            Op::Div(goal) => CageCandidates(Self::for_div(size, goal)),
-            Op::Const(c)  => CageCandidates(vec![SmallVec::new_with(c)]),
+            Op::Add(goal) => CageCandidates(Self::for_add(nums, goal, ncells)).reduced(ken, cage),
+            Op::Mul(0) => CageCandidates(Self::for_zero_mul(nums, ncells)).reduced(ken, cage),
+            Op::Mul(goal) => CageCandidates(Self::for_mul(nums, goal, ncells)).reduced(ken, cage),
+            Op::Sub(goal) if ncells > 2 =>
+                CageCandidates(Self::for_long_sub(nums, goal, ncells)).reduced(ken, cage),
+            Op::Div(goal) if ncells > 2 =>
+                CageCandidates(Self::for_long_div(nums, goal, ncells)).reduced(ken, cage),
+            Op::Sub(goal) => CageCandidates(Self::for_sub(nums, goal)),
+            Op::Div(goal) => CageCandidates(Self::for_div(nums, goal)),
+            Op::Unknown(goal) => {
+                let mut all = Vec::new();
+                for &op in &[Op::Add(goal), Op::Mul(goal), Op::Sub(goal), Op::Div(goal)] {
+                    if ken.allows(op, ncells as usize) && ken.reachable(op, ncells) {
+                        let cage = Cage::new(cage.cells.clone(), op);
+                        all.extend(Self::from_cage(ken, &cage).0);
+                    }
+                }
+                // the same numbers can reach the goal with different operations
+                all.sort();
+                all.dedup();
+                CageCandidates(all)
+            }
+            // nothing can be taken modulo zero
+            Op::Mod(goal) => CageCandidates(Self::for_filter(ken, &cage.cells, &nonneg, None, |s| {
+                s[0] != s[1] && min(s[0], s[1]) != 0 && max(s[0], s[1]) % min(s[0], s[1]) == goal
+            })),
+            Op::Pow(goal) => CageCandidates(Self::for_filter(ken, &cage.cells, &nonneg, None, |s| {
+                s[0].checked_pow(s[1] as u32) == Some(goal) ||
+                    s[1].checked_pow(s[0] as u32) == Some(goal)
+            })),
+            Op::Min(goal) => CageCandidates(Self::for_filter(ken, &cage.cells,
+                                                             &nums.filter(|n| n >= goal),
+                                                             Some(goal), |_| true)),
+            Op::Max(goal) => CageCandidates(Self::for_filter(ken, &cage.cells,
+                                                             &nums.filter(|n| n <= goal),
+                                                             Some(goal), |_| true)),
+            Op::Gcd(goal) => CageCandidates(Self::for_filter(ken, &cage.cells,
+                                                             &nonneg.filter(|n| divides(goal, n)),
+                                                             None, |s| {
+                s.iter().fold(0, |g, &n| gcd(g, n as i64)) == goal as i64
+            })),
+            Op::Lcm(goal) => CageCandidates(Self::for_filter(ken, &cage.cells,
+                                                             &nonneg.filter(|n| divides(n, goal)),
+                                                             None, |s| {
+                // the least common multiple with zero is zero
+                s.iter().fold(1, |l, &n| {
+                    if l == 0 || n == 0 { 0 } else { l / gcd(l, n as i64) * n as i64 }
+                }) == goal as i64
+            })),
+            Op::Concat(goal) => {
+                // the numbers are concatenated in reading order of the cells
+                let mut order = (0..cage.cells.len()).collect::<Vec<_>>();
+                order.sort_by_key(|&i| cage.cells[i]);
+                let seqs = Self::for_concat(&nonneg, &goal.to_string(), ncells);
+                CageCandidates(seqs.iter().map(|seq| {
+                    let mut els = vec![0; seq.len()];
+                    for (k, &i) in order.iter().enumerate() {
+                        els[i] = nums.enc(seq[k]);
+                    }
+                    Self::from_slice(&els)
+                }).collect()).reduced(ken, cage)
+            }
+            Op::Const(c)  => CageCandidates(vec![V::new_with(nums.enc(c))]),
         }
     }
 
     /// Reduce the initial candidates by excluding candidates that have same
-    /// numbers in a single row or column.
-    fn reduced(mut self, cage: &Cage) -> CageCandidates {
-        for (i, &(row1, col1)) in cage.cells.iter().enumerate() {
-            for (j, &(row2, col2)) in cage.cells.iter().enumerate().skip(i + 1) {
-                self.0.retain(|cand| {
-                    if row1 == row2 || col1 == col2 {
-                        cand.get(i) != cand.get(j)
-                    } else {
-                        true
-                    }
-                });
+    /// numbers in a single house.
+    fn reduced(mut self, ken: &KenKen, cage: &Cage) -> CageCandidates<V> {
+        for (i, &cell1) in cage.cells.iter().enumerate() {
+            for (j, &cell2) in cage.cells.iter().enumerate().skip(i + 1) {
+                if ken.same_house(cell1, cell2) {
+                    self.0.retain(|cand| cand.get(i) != cand.get(j));
+                }
             }
         }
         self
@@ -51,19 +151,21 @@ impl CageCandidates {
     }
 
     /// Generate possible sequences for an addition cage.
-    fn for_add(max: u32, goal: u32, len: u32) -> Vec<SmallVec> {
+    fn for_add(nums: &Numbers, goal: i32, len: u32) -> Vec<V> {
         if len == 1 {
-            if goal <= max {
-                vec![SmallVec::new_with(goal)]
+            if nums.contains(goal) {
+                vec![V::new_with(nums.enc(goal))]
             } else {
                 vec![]
             }
         } else {
             let mut all = Vec::new();
-            for i in 1..min(max + 1, goal - len + 2) {
-                let mut candidates = Self::for_add(max, goal - i, len - 1);
+            // the other cells need at least the smallest number each
+            let rest_min = (len - 1) as i32 * nums.vals[0];
+            for &i in nums.vals.iter().take_while(|&&i| i + rest_min <= goal) {
+                let mut candidates = Self::for_add(nums, goal - i, len - 1);
                 for v in &mut candidates {
-                    v.push(i);
+                    v.push(nums.enc(i));
                 }
                 all.extend(candidates)
             }
@@ -72,22 +174,20 @@ impl CageCandidates {
     }
 
     /// Generate possible sequences for a multiplication cage.
-    fn for_mul(max: u32, goal: u32, len: u32) -> Vec<SmallVec> {
+    fn for_mul(nums: &Numbers, goal: i32, len: u32) -> Vec<V> {
         if len == 1 {
-            if goal <= max {
-                vec![SmallVec::new_with(goal)]
+            if nums.contains(goal) {
+                vec![V::new_with(nums.enc(goal))]
             } else {
                 vec![]
             }
         } else {
             let mut all = Vec::new();
-            for i in 1..min(max + 1, goal + 1) {
-                if goal % i != 0 {
-                    continue;
-                }
-                let mut candidates = Self::for_mul(max, goal / i, len - 1);
+            // a goal of zero is handled by `for_zero_mul`
+            for &i in nums.vals.iter().filter(|&&i| i != 0 && divides(i, goal)) {
+                let mut candidates = Self::for_mul(nums, goal / i, len - 1);
                 for v in &mut candidates {
-                    v.push(i);
+                    v.push(nums.enc(i));
                 }
                 all.extend(candidates)
             }
@@ -95,58 +195,417 @@ impl CageCandidates {
         }
     }
 
+    /// Generate possible sequences for a multiplication cage with a goal of
+    /// zero: one of the numbers is zero, the others can be anything.
+    fn for_zero_mul(nums: &Numbers, len: u32) -> Vec<V> {
+        if !nums.contains(0) {
+            return vec![];
+        }
+        let zero = nums.enc(0);
+        if len == 1 {
+            return vec![V::new_with(zero)];
+        }
+        // either the last number is zero, or one of the others
+        let mut all = Self::for_any(nums, len - 1);
+        for v in &mut all {
+            v.push(zero);
+        }
+        for v in Self::for_zero_mul(nums, len - 1) {
+            for &i in nums.vals.iter().filter(|&&i| i != 0) {
+                let mut v = v.clone();
+                v.push(nums.enc(i));
+                all.push(v);
+            }
+        }
+        all
+    }
+
     /// Generate possible sequences for a subtraction cage.
     ///
     /// Subtraction cages always have two cells next to each other, so it is
-    /// very easy to find the candidates.
-    fn for_sub(max: u32, goal: u32) -> Vec<SmallVec> {
-        (1..max-goal+1).flat_map(|i| vec![SmallVec::new_with_two(i, i + goal),
-                                          SmallVec::new_with_two(i + goal, i)]).collect()
+    /// very easy to find the candidates.  The difference can be taken in
+    /// either order, so the sign of the goal doesn't matter.
+    fn for_sub(nums: &Numbers, goal: i32) -> Vec<V> {
+        let goal = goal.abs();
+        nums.vals.iter().filter(|&&i| nums.contains(i + goal))
+                 .flat_map(|&i| vec![V::new_with_two(nums.enc(i), nums.enc(i + goal)),
+                                     V::new_with_two(nums.enc(i + goal), nums.enc(i))]).collect()
     }
 
     /// Generate possible sequences for a division cage.
-    fn for_div(max: u32, goal: u32) -> Vec<SmallVec> {
-        (1..max/goal+1).flat_map(|i| vec![SmallVec::new_with_two(i, i * goal),
-                                          SmallVec::new_with_two(i * goal, i)]).collect()
+    ///
+    /// Zero divided by any other number gives a goal of zero.
+    fn for_div(nums: &Numbers, goal: i32) -> Vec<V> {
+        // with a goal of -1, each pair would be found twice
+        nums.vals.iter().filter(|&&i| i != 0 && (goal != -1 || i > 0) && nums.contains(i * goal))
+                 .flat_map(|&i| vec![V::new_with_two(nums.enc(i), nums.enc(i * goal)),
+                                     V::new_with_two(nums.enc(i * goal), nums.enc(i))]).collect()
+    }
+
+    /// Creates a sequence from a nonempty slice.
+    fn from_slice(els: &[u32]) -> V {
+        let mut new = V::new_with(els[0]);
+        for &el in &els[1..] {
+            new.push(el);
+        }
+        new
+    }
+
+    /// Returns the sequences made by inserting `el` at every position into
+    /// each of the given sequences.
+    fn insert_everywhere(seqs: Vec<V>, el: u32) -> Vec<V> {
+        let mut all = Vec::new();
+        for seq in seqs {
+            for pos in 0..seq.len() + 1 {
+                let mut els = seq.iter().collect::<Vec<_>>();
+                els.insert(pos, el);
+                all.push(Self::from_slice(&els));
+            }
+        }
+        all
+    }
+
+    /// Generate all sequences of `len` (at least 1) numbers taken from
+    /// `nums`.
+    fn for_any(nums: &Numbers, len: u32) -> Vec<V> {
+        let mut all = nums.vals.iter().map(|&i| V::new_with(nums.enc(i))).collect::<Vec<_>>();
+        for _ in 1..len {
+            all = all.iter().flat_map(|v| nums.vals.iter().map(move |&i| {
+                let mut v = v.clone();
+                v.push(nums.enc(i));
+                v
+            })).collect();
+        }
+        all
+    }
+
+    /// Generate all sequences of numbers taken from `nums` for the given
+    /// cells that fulfill the predicate.  Numbers are not repeated within a
+    /// house, and if `must` is given, that number has to appear.
+    fn for_filter<F: Fn(&[i32]) -> bool>(ken: &KenKen, cells: &[(usize, usize)], nums: &Numbers,
+                                         must: Option<i32>, pred: F) -> Vec<V> {
+        let mut all = Vec::new();
+        Self::fill(ken, cells, nums, must, &pred, &mut Vec::with_capacity(cells.len()), &mut all);
+        all
+    }
+
+    /// Recursive part of `for_filter`: tries each number for the next cell
+    /// after those already in `seq`.
+    fn fill<F: Fn(&[i32]) -> bool>(ken: &KenKen, cells: &[(usize, usize)], nums: &Numbers,
+                                   must: Option<i32>, pred: &F, seq: &mut Vec<i32>,
+                                   all: &mut Vec<V>) {
+        let i = seq.len();
+        if i == cells.len() {
+            if pred(seq) {
+                let els = seq.iter().map(|&n| nums.enc(n)).collect::<Vec<_>>();
+                all.push(Self::from_slice(&els));
+            }
+            return;
+        }
+        // the last cell has to take the required number if it is missing
+        let missing = must.filter(|n| i == cells.len() - 1 && !seq.contains(n));
+        for &n in &nums.vals {
+            if missing.is_some_and(|m| m != n) || seq.iter().zip(cells).any(|(&other, &cell)| {
+                other == n && ken.same_house(cell, cells[i])
+            }) {
+                continue;
+            }
+            seq.push(n);
+            Self::fill(ken, cells, nums, must, pred, seq, all);
+            seq.pop();
+        }
+    }
+
+    /// Generate the ways to split the decimal digits of a concatenation
+    /// cage's goal into `len` numbers from `nums`.
+    fn for_concat(nums: &Numbers, digits: &str, len: u32) -> Vec<Vec<i32>> {
+        let mut all = Vec::new();
+        let largest = match nums.vals.last() {
+            Some(&n) => n,
+            None => return all,
+        };
+        for i in 1..digits.len() + 1 {
+            // the last number takes all remaining digits
+            if len == 1 && i < digits.len() {
+                continue;
+            }
+            // only zero itself may start with a zero
+            if digits.starts_with('0') && i > 1 {
+                break;
+            }
+            let num = match digits[..i].parse::<i32>() {
+                Ok(num) if num <= largest => num,
+                _ => break,
+            };
+            if !nums.contains(num) {
+                continue;
+            }
+            if len == 1 {
+                all.push(vec![num]);
+            } else {
+                for mut rest in Self::for_concat(nums, &digits[i..], len - 1) {
+                    rest.insert(0, num);
+                    all.push(rest);
+                }
+            }
+        }
+        all
+    }
+
+    /// Generate possible sequences for a subtraction cage with more than two
+    /// cells: the largest number minus all others is the goal.
+    fn for_long_sub(nums: &Numbers, goal: i32, len: u32) -> Vec<V> {
+        let mut all = Vec::new();
+        for &largest in &nums.vals {
+            // the other numbers add up to largest - goal
+            let rest = Self::for_add(&nums.filter(|n| n <= largest), largest - goal, len - 1);
+            all.extend(Self::insert_everywhere(rest, nums.enc(largest)));
+        }
+        // the largest number can occur twice
+        all.sort();
+        all.dedup();
+        all
+    }
+
+    /// Generate possible sequences for a division cage with more than two
+    /// cells: the largest number divided by all others is the goal.
+    ///
+    /// Since nothing can be divided by zero, zero can only be the largest
+    /// number, which gives a goal of zero with any negative divisors.
+    fn for_long_div(nums: &Numbers, goal: i32, len: u32) -> Vec<V> {
+        if goal == 0 {
+            if !nums.contains(0) {
+                return vec![];
+            }
+            return Self::insert_everywhere(Self::for_any(&nums.filter(|n| n < 0), len - 1),
+                                           nums.enc(0));
+        }
+        let nums = nums.filter(|n| n != 0);
+        let mut all = Vec::new();
+        for &largest in nums.vals.iter().filter(|&&n| divides(goal, n)) {
+            let rest = Self::for_mul(&nums.filter(|n| n <= largest), largest / goal, len - 1);
+            all.extend(Self::insert_everywhere(rest, nums.enc(largest)));
+        }
+        // with a goal of 1, the largest number can occur twice
+        all.sort();
+        all.dedup();
+        all
     }
 }
 
+/// The reason why a candidate was eliminated from a cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// The operation of the cage (given by index) does not allow the number
+    /// in this cell with the remaining combinations.
+    Cage(usize),
+    /// Another cell (row, col) in the same house is fixed to the number.
+    Single(usize, usize),
+    /// Two cells in the same house can only contain the given two numbers, so
+    /// they can't appear anywhere else.
+    Pair((usize, usize), (usize, usize), u32, u32),
+    /// The cell is the only place for the given number in its house (of the
+    /// given kind).
+    HiddenSingle(u32, HouseKind),
+    /// The cells of the subset can only contain its numbers, so they can't
+    /// appear anywhere else in the house (naked triples and quads).
+    NakedSubset(Subset),
+    /// The numbers of the subset can only be in its cells within the house,
+    /// so these cells can't contain other numbers (hidden pairs and triples).
+    HiddenSubset(Subset),
+    /// Every remaining combination of the cage (given by index) puts the
+    /// number into the cage's cells within the house of the given kind.
+    CageHouse(usize, HouseKind),
+    /// The numbers of `count` houses starting at the given index into
+    /// `KenKen::houses` add up to a known sum, and the Add cages within
+    /// leave no place for the number.
+    HouseSum(usize, usize),
+    /// The same for the product of the houses' numbers and the Mul cages
+    /// within.
+    HouseProduct(usize, usize),
+}
+
+/// Maximum number of cells in a naked or hidden subset.
+const MAX_SUBSET: usize = 4;
+
+/// Maximum number of adjacent rows or columns whose sum and product are
+/// considered together by `Constraints::reduce_innies_outies`.
+const MAX_BAND: usize = 3;
+
+/// Up to four cells of a house that are known to contain the same number of
+/// numbers, as found by naked and hidden subsets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Subset {
+    /// The kind of house the cells are in.
+    pub kind: HouseKind,
+    len: usize,
+    cells: [(usize, usize); MAX_SUBSET],
+    nums: [u32; MAX_SUBSET],
+}
+
+impl Subset {
+    fn new<I, J>(kind: HouseKind, cells: I, nums: J) -> Subset
+        where I: Iterator<Item=(usize, usize)>, J: Iterator<Item=u32>
+    {
+        let mut subset = Subset { kind, len: 0, cells: [(0, 0); MAX_SUBSET],
+                                  nums: [0; MAX_SUBSET] };
+        for (i, cell) in cells.take(MAX_SUBSET).enumerate() {
+            subset.cells[i] = cell;
+            subset.len = i + 1;
+        }
+        for (i, el) in nums.take(subset.len).enumerate() {
+            subset.nums[i] = el;
+        }
+        subset
+    }
+
+    /// Returns the (row, col) cells of the subset.
+    pub fn cells(&self) -> &[(usize, usize)] {
+        &self.cells[..self.len]
+    }
+
+    /// Returns the numbers of the subset (stored with the offset, see
+    /// `KenKen::offset`).
+    pub fn nums(&self) -> &[u32] {
+        &self.nums[..self.len]
+    }
+}
+
+/// Finds all combinations of `k` of the given sets whose union has exactly
+/// `k` elements, and returns their indices.
+fn closed_subsets(sets: &[BitSet], k: usize) -> Vec<Vec<usize>> {
+    fn find(sets: &[BitSet], k: usize, start: usize, union: BitSet, chosen: &mut Vec<usize>,
+            found: &mut Vec<Vec<usize>>) {
+        if chosen.len() == k {
+            if union.count() as usize == k {
+                found.push(chosen.clone());
+            }
+            return;
+        }
+        for i in start..sets.len() {
+            let new = union.union(&sets[i]);
+            if new.count() as usize <= k {
+                chosen.push(i);
+                find(sets, k, i + 1, new, chosen, found);
+                chosen.pop();
+            }
+        }
+    }
+    let mut found = Vec::new();
+    find(sets, k, 0, BitSet::new_empty(), &mut Vec::with_capacity(k), &mut found);
+    found
+}
+
+/// A single elimination of a candidate from a cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// Row of the cell.
+    pub row: usize,
+    /// Column of the cell.
+    pub col: usize,
+    /// The number that was removed (stored with the offset, see
+    /// `KenKen::offset`).
+    pub el: u32,
+    /// Why it was removed.
+    pub reason: Reason,
+}
+
+/// A callback that is called for each elimination, after it was done.
+pub type Observer<'a, V = SmallVec> = Box<dyn FnMut(&Step, &Constraints<'a, V>) + 'a>;
+
 /// Represents all candidates for cages and individual cells for a single puzzle.
-pub struct Constraints<'a> {
+///
+/// The type parameter selects the representation of cage candidates; it must
+/// be able to hold the puzzle's numbers and cages (see `KenKen::needs_wide`).
+pub struct Constraints<'a, V: CandVec = SmallVec> {
     ken: &'a KenKen,
     cellcands: Tbl<BitSet>,
-    cagecands: Vec<CageCandidates>,
+    cagecands: Vec<CageCandidates<V>>,
+    observer: Option<Observer<'a, V>>,
+    techniques: Techniques<V>,
+    /// Number of candidates eliminated from cells so far.
+    eliminated: u32,
 }
 
-impl<'a> Constraints<'a> {
-    pub fn empty(ken: &'a KenKen) -> Constraints<'a> {
+impl<'a, V: CandVec> Constraints<'a, V> {
+    /// Creates constraints with all numbers possible for all cells, and no
+    /// cage candidates.  Call `determine_initial` to fill them in.  The
+    /// standard techniques are used (see `Techniques::standard`).
+    pub fn empty(ken: &'a KenKen) -> Constraints<'a, V> {
         Constraints {
-            ken: ken,
-            cellcands: Tbl::square(ken.size, BitSet::new_full(ken.size)),
+            ken,
+            cellcands: Tbl::square(ken.size, ken.digit_set()),
             cagecands: Vec::with_capacity(ken.cages.len()),
+            observer: None,
+            techniques: Techniques::standard(),
+            eliminated: 0,
         }
     }
 
-    pub fn get_cage_candidates(&self, idx: usize) -> &Vec<SmallVec> {
+    /// Sets a callback that is called for each elimination of a candidate.
+    pub fn set_observer(&mut self, observer: Observer<'a, V>) {
+        self.observer = Some(observer);
+    }
+
+    /// Creates constraints with initial candidates determined and reduced
+    /// as far as possible.
+    pub fn new(ken: &'a KenKen) -> Constraints<'a, V> {
+        Constraints::with_techniques(ken, Techniques::standard())
+    }
+
+    /// Creates constraints with initial candidates determined and reduced
+    /// as far as possible by the given techniques.
+    pub fn with_techniques(ken: &'a KenKen, techniques: Techniques<V>) -> Constraints<'a, V> {
+        let mut cons = Constraints::empty(ken);
+        cons.techniques = techniques;
+        cons.determine_initial();
+        while cons.reduce_step().is_some() { }
+        cons
+    }
+
+    /// Returns the techniques used by `reduce`, with their statistics.
+    pub fn techniques(&self) -> &Techniques<V> {
+        &self.techniques
+    }
+
+    /// Returns the techniques used by `reduce`, to enable, disable or
+    /// reorder them.
+    pub fn techniques_mut(&mut self) -> &mut Techniques<V> {
+        &mut self.techniques
+    }
+
+    /// Returns the number of candidates eliminated from cells so far.
+    pub fn eliminated(&self) -> u32 {
+        self.eliminated
+    }
+
+    /// Returns the remaining candidate sequences for the cage with given index.
+    /// Each sequence has one number for each cell, in the order of the cage's cells.
+    pub fn get_cage_candidates(&self, idx: usize) -> &Vec<V> {
         &self.cagecands[idx].0
     }
 
-    fn get(&self, row: usize, col: usize) -> &BitSet {
+    /// Returns the remaining candidate numbers for the cell at (row, col),
+    /// stored with the offset (see `KenKen::offset`).
+    pub fn get(&self, row: usize, col: usize) -> &BitSet {
         self.cellcands.get(row, col)
     }
 
     /// Excludes number `el` from the cell at (row, col).  Updates cage candidates
     /// accordingly, and returns true if anything was changed.
-    fn exclude(&mut self, row: usize, col: usize, el: u32) -> bool {
+    fn exclude(&mut self, row: usize, col: usize, el: u32, reason: Reason) -> bool {
         if self.cellcands.get(row, col).test(el) {
             self.cellcands.get_mut(row, col).clear(el);
+            self.eliminated += 1;
+            self.notify(row, col, el, reason);
 
-            let (cageidx, cellidx) = *self.ken.cell2cage.get(row, col);
-            let rcands = &mut self.cagecands[cageidx];
-            rcands.0.retain(|cand| cand.get(cellidx) != el);
-            for (otheridx, &(row, col)) in self.ken.cages[cageidx].cells.iter().enumerate() {
+            let ken = self.ken;
+            let (cageidx, cellidx) = *ken.cell2cage.get(row, col);
+            self.cagecands[cageidx].0.retain(|cand| cand.get(cellidx) != el);
+            for (otheridx, &(row, col)) in ken.cages[cageidx].cells.iter().enumerate() {
                 if otheridx != cellidx {
-                    self.cellcands.put(row, col, rcands.candidates_for_cell(otheridx));
+                    let new = self.cagecands[cageidx].candidates_for_cell(otheridx);
+                    self.update_from_cage(row, col, cageidx, new);
                 }
             }
             true
@@ -155,59 +614,322 @@ impl<'a> Constraints<'a> {
         }
     }
 
+    /// Replaces the candidates of the cell at (row, col) with those derived
+    /// from the cage's candidates, notifying the observer of each removal.
+    fn update_from_cage(&mut self, row: usize, col: usize, cageidx: usize, new: BitSet) {
+        let old = self.cellcands.get(row, col).clone();
+        self.eliminated += old.count().saturating_sub(new.count());
+        self.cellcands.put(row, col, new);
+        if self.observer.is_some() {
+            for el in old.iter() {
+                if !self.get(row, col).test(el) {
+                    self.notify(row, col, el, Reason::Cage(cageidx));
+                }
+            }
+        }
+    }
+
+    /// Calls the observer, if any, for an elimination.
+    fn notify(&mut self, row: usize, col: usize, el: u32, reason: Reason) {
+        if let Some(mut observer) = self.observer.take() {
+            observer(&Step { row, col, el, reason }, self);
+            self.observer = Some(observer);
+        }
+    }
+
+    /// Returns a human-readable description of an elimination step.  This
+    /// should be called while the constraints are in the state directly after
+    /// the step, e.g. from an observer.
+    pub fn describe(&self, step: &Step) -> String {
+        let cell = |(row, col): (usize, usize)| format!("r{}c{}", row + 1, col + 1);
+        let offset = self.ken.offset();
+        let num = |el: u32| el as i32 + offset;
+        let list = |items: Vec<String>, word: &str| match items.split_last() {
+            Some((last, rest)) if !rest.is_empty() =>
+                format!("{} {} {}", rest.join(", "), word, last),
+            _ => items.join(""),
+        };
+        let because = match step.reason {
+            Reason::Cage(idx) => {
+                // the numbers the remaining combinations put into this cell
+                let cage = &self.ken.cages[idx];
+                let pos = self.ken.cell2cage.get(step.row, step.col).1;
+                let mut allowed = self.cagecands[idx].0.iter().map(|cand| cand.get(pos))
+                                                           .collect::<Vec<_>>();
+                allowed.sort();
+                allowed.dedup();
+                format!("cage at {} ({}) only allows {} there", cell(cage.cells[0]),
+                        cage.operation, if allowed.is_empty() { "nothing".into() } else {
+                            list(allowed.into_iter().map(|el| num(el).to_string()).collect(), "or")
+                        })
+            }
+            Reason::Single(row, col) =>
+                format!("{} is fixed to {}", cell((row, col)), num(step.el)),
+            Reason::Pair(c1, c2, el1, el2) =>
+                format!("{} and {} can only be {} or {}", cell(c1), cell(c2), num(el2), num(el1)),
+            Reason::HiddenSingle(el, kind) =>
+                format!("it is the only place for {} in its {}", num(el), kind),
+            Reason::NakedSubset(ref subset) =>
+                format!("{} can only be {}",
+                        list(subset.cells().iter().map(|&c| cell(c)).collect(), "and"),
+                        list(subset.nums().iter().map(|&el| num(el).to_string()).collect(), "or")),
+            Reason::HiddenSubset(ref subset) =>
+                format!("{} can only be in {} in their {}",
+                        list(subset.nums().iter().map(|&el| num(el).to_string()).collect(), "and"),
+                        list(subset.cells().iter().map(|&c| cell(c)).collect(), "or"),
+                        subset.kind),
+            Reason::CageHouse(idx, kind) => {
+                let cage = &self.ken.cages[idx];
+                format!("cage at {} ({}) must contain {} in this {}", cell(cage.cells[0]),
+                        cage.operation, num(step.el), kind)
+            }
+            Reason::HouseSum(first, count) | Reason::HouseProduct(first, count) => {
+                let size = self.ken.size;
+                let house = &self.ken.houses()[first];
+                let which = match house.kind {
+                    HouseKind::Row | HouseKind::Column => {
+                        let idx = if house.kind == HouseKind::Row { first } else { first - size };
+                        if count == 1 {
+                            format!("{} {}", house.kind, idx + 1)
+                        } else {
+                            format!("{}s {}-{}", house.kind, idx + 1, idx + count)
+                        }
+                    }
+                    HouseKind::Region => format!("the region at {}", cell(house.cells[0])),
+                    kind => format!("the {}", kind),
+                };
+                let digits = self.ken.digits();
+                if let Reason::HouseSum(..) = step.reason {
+                    let total = count as i64 * digits.iter().map(|&n| n as i64).sum::<i64>();
+                    format!("the numbers in {} add up to {}, and the Add cages there leave \
+                             no room for it", which, total)
+                } else {
+                    let total = digits.iter().map(|&n| n as i64).product::<i64>()
+                                      .pow(count as u32);
+                    format!("the numbers in {} multiply to {}, and the Mul cages there leave \
+                             no room for it", which, total)
+                }
+            }
+        };
+        format!("cell {}: {} removed because {}", cell((step.row, step.col)), num(step.el),
+                because)
+    }
+
     /// Determines initial constraints from cage candidates.
     pub fn determine_initial(&mut self) {
-        for cage in &self.ken.cages {
-            let new = CageCandidates::from_cage(self.ken, cage);
+        let ken = self.ken;
+        for (cageidx, cage) in ken.cages.iter().enumerate() {
+            self.cagecands.push(CageCandidates::from_cage(ken, cage));
             for (cellidx, &(row, col)) in cage.cells.iter().enumerate() {
-                self.cellcands.put(row, col, new.candidates_for_cell(cellidx));
+                let new = self.cagecands[cageidx].candidates_for_cell(cellidx);
+                self.update_from_cage(row, col, cageidx, new);
             }
-            self.cagecands.push(new);
         }
     }
 
-    /// Tries to reduce constraints by removing known values within a
-    /// row or column from other cages in that row or column.
+    /// Returns true if every cell has exactly one candidate left.
+    pub fn is_solved(&self) -> bool {
+        self.cellcands.as_vec().iter().all(|set| set.count() == 1)
+    }
+
+    /// Tries to reduce constraints by applying each enabled technique once
+    /// (by default, removing known values within a house from other cages
+    /// in that house, and looking for naked pairs).
     ///
     /// Returns true if anything was changed.
     pub fn reduce(&mut self) -> bool {
+        let mut techniques = mem::take(&mut self.techniques);
+        let changed = techniques.round(self);
+        self.techniques = techniques;
+        changed
+    }
+
+    /// Applies the enabled techniques in order until one of them changes
+    /// something, and returns its name.  This allows to find out which
+    /// techniques are really needed.
+    pub fn reduce_step(&mut self) -> Option<&'static str> {
+        let mut techniques = mem::take(&mut self.techniques);
+        let name = techniques.step(self);
+        self.techniques = techniques;
+        name
+    }
+
+    /// Removes known values (cells with only one candidate) from other cells
+    /// in the same house.
+    ///
+    /// Returns true if anything was changed.
+    pub fn reduce_singles(&mut self) -> bool {
+        let ken = self.ken;
         let mut changed = false;
-        for row in 0..self.ken.size {
-            for col in 0..self.ken.size {
-                let n = self.get(row, col).count();
-                // remove known values from other cells in same row/col
-                if n == 1 {
+        for house in ken.houses() {
+            for &(row, col) in &house.cells {
+                if self.get(row, col).count() == 1 {
                     let el = self.get(row, col).get_one();
-                    for other in 0..self.ken.size {
-                        if other != col {
-                            changed |= self.exclude(row, other, el);
-                        }
-                        if other != row {
-                            changed |= self.exclude(other, col, el);
+                    for &(orow, ocol) in &house.cells {
+                        if (orow, ocol) != (row, col) {
+                            changed |= self.exclude(orow, ocol, el, Reason::Single(row, col));
                         }
                     }
                 }
-                // remove values from other cells in same row/col if two cells are
-                // known to have the same two possibilities
-                else if n == 2 {
-                    let (el1, el2) = self.get(row, col).get_two();
-                    for scol in col+1..self.ken.size {
-                        if self.get(row, col) == self.get(row, scol) {
-                            for ocol in 0..self.ken.size {
-                                if ocol != col && ocol != scol {
-                                    changed |= self.exclude(row, ocol, el1);
-                                    changed |= self.exclude(row, ocol, el2);
-                                }
+            }
+        }
+        changed
+    }
+
+    /// Removes values from other cells in the same house if two cells are
+    /// known to have the same two possibilities (naked pairs).
+    ///
+    /// Returns true if anything was changed.
+    pub fn reduce_pairs(&mut self) -> bool {
+        let ken = self.ken;
+        let mut changed = false;
+        for house in ken.houses() {
+            let cells = &house.cells;
+            for (i, &(row, col)) in cells.iter().enumerate() {
+                if self.get(row, col).count() != 2 {
+                    continue;
+                }
+                let (el1, el2) = self.get(row, col).get_two();
+                for &(srow, scol) in &cells[i+1..] {
+                    if self.get(row, col) == self.get(srow, scol) {
+                        let reason = Reason::Pair((row, col), (srow, scol), el1, el2);
+                        for &(orow, ocol) in cells {
+                            if (orow, ocol) != (row, col) && (orow, ocol) != (srow, scol) {
+                                changed |= self.exclude(orow, ocol, el1, reason);
+                                changed |= self.exclude(orow, ocol, el2, reason);
                             }
                         }
                     }
-                    for srow in row+1..self.ken.size {
-                        if self.get(row, col) == self.get(srow, col) {
-                            for orow in 0..self.ken.size {
-                                if orow != row && orow != srow {
-                                    changed |= self.exclude(orow, col, el1);
-                                    changed |= self.exclude(orow, col, el2);
-                                }
+                }
+            }
+        }
+        changed
+    }
+
+    /// Fixes cells which are the only place for a number within one of their
+    /// houses (hidden singles), by removing all other candidates.
+    ///
+    /// Returns true if anything was changed.
+    pub fn reduce_hidden_singles(&mut self) -> bool {
+        let ken = self.ken;
+        let mut changed = false;
+        let digits = ken.digit_set();
+        for house in ken.houses() {
+            for el in digits.iter() {
+                let mut places = house.cells.iter().filter(|&&(row, col)| self.get(row, col).test(el));
+                if let (Some(&(row, col)), None) = (places.next(), places.next()) {
+                    changed |= self.fix(row, col, el, house.kind);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Removes numbers from other cells in the same house if `k` cells can
+    /// only contain `k` numbers altogether (naked subsets, e.g. triples for
+    /// `k` = 3).
+    ///
+    /// Returns true if anything was changed.
+    pub fn reduce_naked_subsets(&mut self, k: usize) -> bool {
+        let ken = self.ken;
+        let mut changed = false;
+        for house in ken.houses() {
+            let cells = house.cells.iter().cloned().filter(|&(row, col)| {
+                (2..=k).contains(&(self.get(row, col).count() as usize))
+            }).collect::<Vec<_>>();
+            let sets = cells.iter().map(|&(row, col)| self.get(row, col).clone())
+                                   .collect::<Vec<_>>();
+            for idxs in closed_subsets(&sets, k) {
+                let nums = idxs.iter().fold(BitSet::new_empty(), |set, &i| set.union(&sets[i]));
+                let subset = Subset::new(house.kind, idxs.iter().map(|&i| cells[i]), nums.iter());
+                for &(row, col) in &house.cells {
+                    if !subset.cells().contains(&(row, col)) {
+                        for el in nums.iter() {
+                            changed |= self.exclude(row, col, el, Reason::NakedSubset(subset));
+                        }
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    /// Removes other numbers from `k` cells in a house if `k` numbers can
+    /// only be placed in these cells (hidden subsets, e.g. pairs for `k` = 2).
+    ///
+    /// Returns true if anything was changed.
+    pub fn reduce_hidden_subsets(&mut self, k: usize) -> bool {
+        let ken = self.ken;
+        let mut changed = false;
+        let digits = ken.digit_set();
+        for house in ken.houses() {
+            // for each number, the set of indices of cells in the house
+            // where it can be placed
+            let places = digits.iter().map(|el| {
+                let mut set = BitSet::new_empty();
+                for (i, &(row, col)) in house.cells.iter().enumerate() {
+                    if self.get(row, col).test(el) {
+                        set.set(i as u32);
+                    }
+                }
+                (el, set)
+            }).filter(|(_, set)| (2..=k).contains(&(set.count() as usize))).collect::<Vec<_>>();
+            let sets = places.iter().map(|(_, set)| set.clone()).collect::<Vec<_>>();
+            for idxs in closed_subsets(&sets, k) {
+                let nums = idxs.iter().map(|&i| places[i].0).collect::<Vec<_>>();
+                let cells = idxs.iter().fold(BitSet::new_empty(), |set, &i| set.union(&sets[i]));
+                let subset = Subset::new(house.kind, cells.iter().map(|i| house.cells[i as usize]),
+                                         nums.iter().cloned());
+                for &(row, col) in subset.cells() {
+                    for el in self.get(row, col).iter() {
+                        if !nums.contains(&el) {
+                            changed |= self.exclude(row, col, el, Reason::HiddenSubset(subset));
+                        }
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    /// Removes a number from the cells of a house outside a cage if every
+    /// remaining combination of the cage puts the number into one of the
+    /// cage's cells in that house.
+    ///
+    /// Returns true if anything was changed.
+    pub fn reduce_cage_houses(&mut self) -> bool {
+        let ken = self.ken;
+        let size = ken.size;
+        let houses = ken.houses();
+        let mut changed = false;
+        for (cageidx, cage) in ken.cages.iter().enumerate() {
+            if cage.cells.len() < 2 || self.cagecands[cageidx].0.is_empty() {
+                continue;
+            }
+            // the houses are the rows, then the columns, then the others
+            let mut hidxs = cage.cells.iter().flat_map(|&(row, col)| vec![row, size + col])
+                                             .collect::<Vec<_>>();
+            hidxs.extend((2 * size..houses.len()).filter(|&h| {
+                cage.cells.iter().any(|cell| houses[h].cells.contains(cell))
+            }));
+            hidxs.sort();
+            hidxs.dedup();
+            for house in hidxs.into_iter().map(|h| &houses[h]) {
+                let idxs = (0..cage.cells.len()).filter(|&i| house.cells.contains(&cage.cells[i]))
+                                                .collect::<Vec<_>>();
+                let nums = idxs.iter().fold(BitSet::new_empty(), |set, &i| {
+                    let (row, col) = cage.cells[i];
+                    set.union(self.get(row, col))
+                });
+                for el in nums.iter() {
+                    let must = self.cagecands[cageidx].0.iter().all(|cand| {
+                        idxs.iter().any(|&i| cand.get(i) == el)
+                    });
+                    if must {
+                        for &(row, col) in &house.cells {
+                            if !cage.cells.contains(&(row, col)) {
+                                changed |= self.exclude(row, col, el,
+                                                        Reason::CageHouse(cageidx, house.kind));
                             }
                         }
                     }
@@ -216,23 +938,483 @@ impl<'a> Constraints<'a> {
         }
         changed
     }
+
+    /// Uses that the numbers of every house add up to the sum of all numbers
+    /// and multiply to their product ("innies and outies").  For bands of up
+    /// to `MAX_BAND` adjacent rows or columns, and for each other house, the
+    /// goals of the Add cages that lie inside give the sum of the remaining
+    /// cells.  An Add cage with fewer cells outside the band than inside is
+    /// counted as a whole, and its cells outside are subtracted instead.
+    /// Numbers that can't reach this sum together with the bounds of the
+    /// other cells are removed.  The same is done with the products of Mul
+    /// cages, if all numbers are positive.
+    ///
+    /// Returns true if anything was changed.
+    pub fn reduce_innies_outies(&mut self) -> bool {
+        let ken = self.ken;
+        let size = ken.size;
+        let houses = ken.houses();
+        let digits = ken.digits();
+        let sum = digits.iter().map(|&n| n as i64).sum::<i64>();
+        // with zero or negative numbers, bounds don't work for products
+        let product = if digits[0] > 0 {
+            digits.iter().try_fold(1i64, |p, &n| p.checked_mul(n as i64))
+        } else {
+            None
+        };
+        // groups of houses given by index of the first one and count; the
+        // houses are the rows, then the columns, then the others
+        let mut groups = Vec::new();
+        for count in 1..min(MAX_BAND, size - 1) + 1 {
+            for first in 0..size - count + 1 {
+                groups.push((first, count));
+                groups.push((size + first, count));
+            }
+        }
+        groups.extend((2 * size..houses.len()).map(|h| (h, 1)));
+        let mut changed = false;
+        // the number of cells of each cage inside the group
+        let mut inside = vec![0; ken.cages.len()];
+        for (first, count) in groups {
+            for (n, cage) in inside.iter_mut().zip(&ken.cages) {
+                *n = cage.cells.iter().filter(|&&cell| self.in_houses(first, count, cell)).count();
+            }
+            changed |= self.reduce_house_sum(first, count, &inside, count as i64 * sum);
+            if let Some(total) = product.and_then(|p| p.checked_pow(count as u32)) {
+                changed |= self.reduce_house_product(first, count, &inside, total);
+            }
+        }
+        changed
+    }
+
+    /// Returns true if the cell is in one of `count` houses starting at the
+    /// given index into `KenKen::houses`.
+    fn in_houses(&self, first: usize, count: usize, (row, col): (usize, usize)) -> bool {
+        let size = self.ken.size;
+        if first < size {
+            row >= first && row < first + count
+        } else if first < 2 * size {
+            col >= first - size && col < first - size + count
+        } else {
+            self.ken.houses()[first..first + count].iter().any(|h| h.cells.contains(&(row, col)))
+        }
+    }
+
+    /// Applies the sum part of `reduce_innies_outies` to `count` houses
+    /// starting at index `first`, given the number of cells of each cage
+    /// inside them and the sum of all their numbers.
+    fn reduce_house_sum(&mut self, first: usize, count: usize, inside: &[usize],
+                        mut total: i64) -> bool {
+        let ken = self.ken;
+        // an Add cage with more cells inside than outside is used as a whole
+        let whole = |idx: usize| match ken.cages[idx].operation {
+            Op::Add(_) => 2 * inside[idx] > ken.cages[idx].cells.len(),
+            _ => false,
+        };
+        if !(0..ken.cages.len()).any(whole) {
+            return false;
+        }
+        let offset = ken.offset() as i64;
+        // the cells whose numbers are added (sign 1) or subtracted (sign -1)
+        // to give the total, and the range of their contribution
+        let mut terms = Vec::new();
+        for (idx, cage) in ken.cages.iter().enumerate().filter(|&(idx, _)| inside[idx] > 0) {
+            let whole = whole(idx);
+            if let (true, Op::Add(goal)) = (whole, cage.operation) {
+                total -= goal as i64;
+                if inside[idx] == cage.cells.len() {
+                    continue;
+                }
+            }
+            for &(row, col) in &cage.cells {
+                if self.in_houses(first, count, (row, col)) != whole {
+                    let (lo, hi) = match self.get(row, col).bounds() {
+                        Some((lo, hi)) => (lo as i64 + offset, hi as i64 + offset),
+                        None => return false,
+                    };
+                    terms.push(if whole { ((row, col), -1, -hi, -lo) }
+                               else { ((row, col), 1, lo, hi) });
+                }
+            }
+        }
+        let lo_sum = terms.iter().map(|t| t.2).sum::<i64>();
+        let hi_sum = terms.iter().map(|t| t.3).sum::<i64>();
+        let mut changed = false;
+        for ((row, col), sign, lo, hi) in terms {
+            // the range the other cells can reach; since the sum is monotonic,
+            // nothing can be removed if the cell's bounds are fine
+            let (rest_lo, rest_hi) = (lo_sum - lo, hi_sum - hi);
+            if total - hi >= rest_lo && total - lo <= rest_hi {
+                continue;
+            }
+            for el in self.get(row, col).iter() {
+                let rest = total - sign * (el as i64 + offset);
+                if rest < rest_lo || rest > rest_hi {
+                    changed |= self.exclude(row, col, el, Reason::HouseSum(first, count));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Applies the product part of `reduce_innies_outies` in the same way.
+    /// All numbers are positive here, so the offset is zero.
+    fn reduce_house_product(&mut self, first: usize, count: usize, inside: &[usize],
+                            mut total: i64) -> bool {
+        let ken = self.ken;
+        let whole = |idx: usize| match ken.cages[idx].operation {
+            Op::Mul(_) => inside[idx] == ken.cages[idx].cells.len(),
+            _ => false,
+        };
+        if !(0..ken.cages.len()).any(whole) {
+            return false;
+        }
+        // the cells whose numbers multiply to the total, and their bounds
+        let mut terms = Vec::new();
+        for (idx, cage) in ken.cages.iter().enumerate().filter(|&(idx, _)| inside[idx] > 0) {
+            if let (true, Op::Mul(goal)) = (whole(idx), cage.operation) {
+                if goal <= 0 || total % goal as i64 != 0 {
+                    return false;
+                }
+                total /= goal as i64;
+                continue;
+            }
+            for &(row, col) in &cage.cells {
+                if self.in_houses(first, count, (row, col)) {
+                    match self.get(row, col).bounds() {
+                        Some((lo, hi)) => terms.push(((row, col), lo as i64, hi as i64)),
+                        None => return false,
+                    }
+                }
+            }
+        }
+        let lo_prod = terms.iter().try_fold(1i64, |p, t| p.checked_mul(t.1));
+        let hi_prod = terms.iter().try_fold(1i64, |p, t| p.checked_mul(t.2));
+        let (lo_prod, hi_prod) = match (lo_prod, hi_prod) {
+            (Some(lo), Some(hi)) => (lo, hi),
+            _ => return false,
+        };
+        let mut changed = false;
+        for ((row, col), lo, hi) in terms {
+            // the range the other cells can reach
+            let (rest_lo, rest_hi) = (lo_prod / lo, hi_prod / hi);
+            for el in self.get(row, col).iter() {
+                let (n, rest) = (el as i64, total / el as i64);
+                if total % n != 0 || rest < rest_lo || rest > rest_hi {
+                    changed |= self.exclude(row, col, el, Reason::HouseProduct(first, count));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Removes all candidates except `el` from the cell at (row, col), which
+    /// is the only place for `el` in a house of the given kind.
+    ///
+    /// Returns true if anything was changed.
+    fn fix(&mut self, row: usize, col: usize, el: u32, kind: HouseKind) -> bool {
+        let mut changed = false;
+        for other in self.get(row, col).iter() {
+            if other != el {
+                changed |= self.exclude(row, col, other, Reason::HiddenSingle(el, kind));
+            }
+        }
+        changed
+    }
 }
 
 /// Displays a table of candidates for each cell.
-impl<'a> fmt::Display for Constraints<'a> {
+impl<'a, V: CandVec> fmt::Display for Constraints<'a, V> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let size = self.ken.size;
+        let offset = self.ken.offset();
         let nums = &self.cellcands.as_vec();
-        let v = nums.iter().map(|set| set.to_string()).collect::<Vec<_>>();
-        let sep1 = "+".to_string() + &repeat('-').take(size+2).collect::<String>();
-        let sep = repeat(&*sep1).take(size).collect::<String>() + "+\n";
+        // one character per number, also for numbers > 9 (see BitSet);
+        // negative numbers need a list instead
+        let v = nums.iter().map(|set| if offset == 0 {
+            format!("{:#}", set)
+        } else {
+            set.iter().map(|el| (el as i32 + offset).to_string()).collect::<Vec<_>>().join(",")
+        }).collect::<Vec<_>>();
+        let width = v.iter().map(|s| s.len()).max().unwrap_or(0).max(size);
+        let sep1 = "+".to_string() + &"-".repeat(width+2);
+        let sep = sep1.repeat(size) + "+\n";
         for row in v.chunks(size) {
-            try!(f.write_str(&sep));
+            f.write_str(&sep)?;
             for cell in row {
-                try!(write!(f, "| {0:1$} ", cell, size));
+                write!(f, "| {0:1$} ", cell, width)?;
             }
-            try!(f.write_str("|\n"));
+            f.write_str("|\n")?;
         }
         f.write_str(&sep)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use search::{search_with, Strategy};
+    use super::*;
+
+    const EXAMPLES: &[&str] = &[include_str!("../examples/test4.ken"),
+                                include_str!("../examples/test6-1.ken"),
+                                include_str!("../examples/test6-2.ken"),
+                                include_str!("../examples/test6-3.ken"),
+                                include_str!("../examples/test9-1.ken"),
+                                include_str!("../examples/test9-2.ken"),
+                                include_str!("../examples/test9-3.ken")];
+
+    /// A puzzle with four solutions.
+    const MULTIPLE: &str = "abbc\na2cc\nddef\n4def\n\na: 4+\nb: 2-\nc: 8+\nd: 6+\ne: 4+\nf: 6+\n";
+
+    /// Sets the candidates of all cells from rows of numbers like "12 34".
+    fn set_candidates<V: CandVec>(cons: &mut Constraints<V>, rows: &[&str]) {
+        for (row, line) in rows.iter().enumerate() {
+            for (col, cell) in line.split_whitespace().enumerate() {
+                let mut set = BitSet::new_empty();
+                for ch in cell.chars() {
+                    set.set(ch.to_digit(10).unwrap() - cons.ken.offset() as u32);
+                }
+                cons.cellcands.put(row, col, set);
+            }
+        }
+    }
+
+    /// Returns the candidates of all cells, in the format of `set_candidates`.
+    fn candidates<V: CandVec>(cons: &Constraints<V>) -> Vec<String> {
+        let size = cons.ken.size;
+        (0..size).map(|row| (0..size).map(|col| format!("{:#}", cons.get(row, col)))
+                                     .collect::<Vec<_>>().join(" ")).collect()
+    }
+
+    /// Applies a reduction to the given candidates of a puzzle.  Returns the
+    /// new candidates and the eliminations.
+    fn reduce_puzzle<F>(ken: &KenKen, rows: &[&str], reduce: F) -> (Vec<String>, Vec<Step>)
+        where F: FnOnce(&mut Constraints) -> bool
+    {
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let mut cons = Constraints::empty(ken);
+        cons.determine_initial();
+        set_candidates(&mut cons, rows);
+        let seen = steps.clone();
+        cons.set_observer(Box::new(move |step, _| seen.borrow_mut().push(*step)));
+        assert!(reduce(&mut cons));
+        let result = candidates(&cons);
+        drop(cons);
+        let steps = steps.borrow().clone();
+        (result, steps)
+    }
+
+    /// Applies a reduction to the given candidates of a puzzle that has a
+    /// constant in every cell, so that the cages don't get in the way.
+    fn reduce_open<F>(rows: &[&str], reduce: F) -> (Vec<String>, Vec<Step>)
+        where F: FnOnce(&mut Constraints) -> bool
+    {
+        let mut ken = KenKen::new(rows.len()).unwrap();
+        for row in 0..rows.len() {
+            for col in 0..rows.len() {
+                ken.add_cage(Cage::new(vec![(row, col)], Op::Const(1))).unwrap();
+            }
+        }
+        reduce_puzzle(&ken, rows, reduce)
+    }
+
+    /// Returns (row, col, number) of the eliminations.
+    fn removed(steps: &[Step]) -> Vec<(usize, usize, u32)> {
+        steps.iter().map(|step| (step.row, step.col, step.el)).collect()
+    }
+
+    #[test]
+    fn describe_cage() {
+        for text in EXAMPLES {
+            let ken = text.parse::<KenKen>().unwrap();
+            let steps = Rc::new(RefCell::new(Vec::new()));
+            let mut cons = Constraints::<SmallVec>::empty(&ken);
+            let seen = steps.clone();
+            let offset = ken.offset();
+            cons.set_observer(Box::new(move |step, cons| {
+                if let Reason::Cage(_) = step.reason {
+                    seen.borrow_mut().push((step.el as i32 + offset, cons.describe(step)));
+                }
+            }));
+            cons.determine_initial();
+            while cons.reduce() { }
+            assert!(!steps.borrow().is_empty());
+            for &(removed, ref desc) in steps.borrow().iter() {
+                // the removed number is not among the allowed ones
+                let allowed = &desc[desc.find("only allows ").unwrap() + 12..desc.len() - 6];
+                let nums = allowed.split(", ").flat_map(|s| s.split(" or "))
+                                  .map(|n| n.parse::<i32>().unwrap()).collect::<Vec<_>>();
+                assert!(!nums.contains(&removed), "{}", desc);
+            }
+        }
+    }
+
+    /// Applies naked (or hidden) subsets of `k` cells to the given
+    /// candidates, and checks that all eliminations are due to the subset
+    /// with the given house kind, cells and numbers.  Returns the new
+    /// candidates and (row, col, number) of the eliminations.
+    fn find_subset(rows: &[&str], naked: bool, k: usize, kind: HouseKind,
+                   cells: &[(usize, usize)], nums: &[u32])
+                   -> (Vec<String>, Vec<(usize, usize, u32)>) {
+        let (result, steps) = reduce_open(rows, |cons| if naked {
+            cons.reduce_naked_subsets(k)
+        } else {
+            cons.reduce_hidden_subsets(k)
+        });
+        for step in &steps {
+            let subset = match (naked, step.reason) {
+                (true, Reason::NakedSubset(subset)) | (false, Reason::HiddenSubset(subset)) =>
+                    subset,
+                (_, reason) => panic!("unexpected reason {:?}", reason),
+            };
+            assert_eq!(subset.kind, kind);
+            assert_eq!(subset.cells(), cells);
+            assert_eq!(subset.nums(), nums);
+        }
+        (result, removed(&steps))
+    }
+
+    #[test]
+    fn hidden_pair() {
+        // 1 and 2 can only go into the first two cells of the first row
+        let (result, removed) = find_subset(&["1234 12 34 34", "1234 1234 1234 1234",
+                                              "1234 1234 1234 1234", "1234 1234 1234 1234"],
+                                            false, 2, HouseKind::Row, &[(0, 0), (0, 1)], &[1, 2]);
+        assert_eq!(result[0], "12 12 34 34");
+        assert_eq!(removed, [(0, 0, 3), (0, 0, 4)]);
+    }
+
+    #[test]
+    fn hidden_triple() {
+        // 1, 2 and 3 can only go into rows 1, 3 and 4 of the second column
+        let (result, removed) = find_subset(&["12345 1245 12345 12345 12345",
+                                              "12345 45 12345 12345 12345",
+                                              "12345 12345 12345 12345 12345",
+                                              "12345 1345 12345 12345 12345",
+                                              "12345 45 12345 12345 12345"],
+                                            false, 3, HouseKind::Column,
+                                            &[(0, 1), (2, 1), (3, 1)], &[1, 2, 3]);
+        let column = result.iter().map(|row| row.split(' ').nth(1).unwrap()).collect::<Vec<_>>();
+        assert_eq!(column, ["12", "45", "123", "13", "45"]);
+        assert_eq!(removed, [(0, 1, 4), (0, 1, 5), (2, 1, 4), (2, 1, 5), (3, 1, 4), (3, 1, 5)]);
+    }
+
+    #[test]
+    fn naked_triple() {
+        // the first three cells of the last row can only contain 1, 2 and 3
+        let (result, removed) = find_subset(&["12345 12345 12345 12345 12345",
+                                              "12345 12345 12345 12345 12345",
+                                              "12345 12345 12345 12345 12345",
+                                              "12345 12345 12345 12345 12345",
+                                              "12 23 13 12345 1345"],
+                                            true, 3, HouseKind::Row,
+                                            &[(4, 0), (4, 1), (4, 2)], &[1, 2, 3]);
+        assert_eq!(result[4], "12 23 13 45 45");
+        assert_eq!(result[..4], ["12345 12345 12345 12345 12345"; 4]);
+        assert_eq!(removed, [(4, 3, 1), (4, 3, 2), (4, 3, 3), (4, 4, 1), (4, 4, 3)]);
+    }
+
+    #[test]
+    fn naked_quad() {
+        // four cells of the third row can only contain 1 to 4
+        let rest = "123456 123456 123456 123456 123456 123456";
+        let rows = [rest, rest, "12 123456 23 34 14 1256", rest, rest, rest];
+        let (result, removed) = find_subset(&rows, true, 4, HouseKind::Row,
+                                            &[(2, 0), (2, 2), (2, 3), (2, 4)], &[1, 2, 3, 4]);
+        assert_eq!(result[2], "12 56 23 34 14 56");
+        assert_eq!(removed, [(2, 1, 1), (2, 1, 2), (2, 1, 3), (2, 1, 4), (2, 5, 1), (2, 5, 2)]);
+        // a triple is not found in the same cells
+        let (_, steps) = reduce_open(&rows, |cons| { cons.reduce_naked_subsets(3); true });
+        assert!(steps.is_empty());
+    }
+
+    /// Applies innies and outies to the given candidates of a puzzle.
+    /// Returns the new candidates and (row, col, number) of the eliminations
+    /// with the given reason; the others follow from them, through the cages
+    /// or other rows and columns.
+    fn innies_outies(text: &str, rows: &[&str], reason: Reason)
+                     -> (Vec<String>, Vec<(usize, usize, u32)>) {
+        let ken = text.parse::<KenKen>().unwrap();
+        let (result, steps) = reduce_puzzle(&ken, rows, |cons| cons.reduce_innies_outies());
+        let steps = steps.into_iter().filter(|step| step.reason == reason).collect::<Vec<_>>();
+        (result, removed(&steps))
+    }
+
+    #[test]
+    fn innie_sum() {
+        // the 6+ cage lies in the first row, which adds up to 10
+        let (result, removed) = innies_outies("aaab\nccdb\needf\ngg2f\n\na: 6+\nb: 1-\nc: 1-\n\
+                                               d: 1-\ne: 1-\nf: 1-\ng: 1-\n",
+                                              &["1234 1234 1234 1234"; 4], Reason::HouseSum(0, 1));
+        assert_eq!(result[0], "1234 1234 1234 4");
+        assert_eq!(removed, [(0, 3, 1), (0, 3, 2), (0, 3, 3)]);
+    }
+
+    #[test]
+    fn outie_sum() {
+        // the 7+ cage mostly lies in the first row, with one cell below
+        let (result, removed) = innies_outies("aaab\nccab\nddee\nffgg\n\na: 7+\nb: 1-\nc: 1-\n\
+                                               d: 1-\ne: 1-\nf: 1-\ng: 1-\n",
+                                              &["1234 1234 1234 1234"; 4], Reason::HouseSum(0, 1));
+        // so the cell on the right is 3 more than the one below it; the
+        // other changes follow through the cages
+        assert_eq!(result[..2], ["123 123 23 4", "234 234 1 3"]);
+        assert_eq!(removed, [(1, 2, 2), (1, 2, 3), (1, 2, 4), (0, 3, 1), (0, 3, 2), (0, 3, 3)]);
+    }
+
+    #[test]
+    fn innie_product() {
+        // the 6* cage lies in the first row, which multiplies to 24
+        let (result, removed) = innies_outies("aaab\nccdb\needf\ngg2f\n\na: 6*\nb: 1-\nc: 1-\n\
+                                               d: 1-\ne: 1-\nf: 1-\ng: 1-\n",
+                                              &["1234 1234 1234 1234"; 4],
+                                              Reason::HouseProduct(0, 1));
+        assert_eq!(result[0], "1234 1234 1234 4");
+        assert_eq!(removed, [(0, 3, 1), (0, 3, 2), (0, 3, 3)]);
+    }
+
+    #[test]
+    fn cage_house() {
+        // the 10+ cage is 4, 3, 3 or 2, 4, 4, so it puts a 4 into the first
+        // row and the first column
+        let ken = "aabb\naccd\nefgd\nefg1\n\na: 10+\nb: 1-\nc: 1-\nd: 1-\ne: 1-\nf: 1-\n\
+                   g: 1-\n".parse::<KenKen>().unwrap();
+        let (result, steps) = reduce_puzzle(&ken, &["24 34 1234 1234", "34 1234 1234 1234",
+                                                    "1234 1234 1234 1234", "1234 1234 1234 1"],
+                                            |cons| cons.reduce_cage_houses());
+        assert!(!result[0].split(' ').skip(2).any(|cell| cell.contains('4')));
+        // other cages may go on from there
+        let cage = ken.cage_at(0, 0).unwrap();
+        let removed = steps.iter().filter_map(|step| match step.reason {
+            Reason::CageHouse(idx, kind) if idx == cage =>
+                Some((step.row, step.col, step.el, kind)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(removed, [(0, 2, 4, HouseKind::Row), (0, 3, 4, HouseKind::Row),
+                             (2, 0, 4, HouseKind::Column), (3, 0, 4, HouseKind::Column)]);
+    }
+
+    #[test]
+    fn subsets_keep_solutions() {
+        let subsets = ["hidden-pairs", "naked-triples", "hidden-triples", "naked-quads"];
+        for text in EXAMPLES.iter().chain(&[MULTIPLE]) {
+            let ken = text.parse::<KenKen>().unwrap();
+            let mut without = Techniques::standard();
+            for name in &subsets {
+                without.set_enabled(name, false);
+            }
+            let plain = Constraints::<SmallVec>::with_techniques(&ken, without);
+            let full = Constraints::<SmallVec>::new(&ken);
+            let expected = search_with(&ken, &plain, None, true, Strategy::Fixed);
+            let found = search_with(&ken, &full, None, true, Strategy::Fixed);
+            assert_eq!(found.count, expected.count);
+            let grids = |sols: &[Tbl<i32>]| sols.iter().map(|sol| sol.as_vec().clone())
+                                                .collect::<Vec<_>>();
+            assert_eq!(grids(&found.solutions), grids(&expected.solutions));
+            assert!(full.eliminated() >= plain.eliminated());
+        }
+    }
+}
@@ -0,0 +1,274 @@
+// KenKen puzzle solver, (c) 2016 Georg Brandl.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use Op;
+
+/// A position in a `.ken` file, with 1-based line and column numbers.
+///
+/// For errors concerning a cell or cage, this is the position of the cell
+/// (or the cage's first cell) in the cage map, i.e. line = row + 1 and
+/// column = col + 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Pos {
+    /// Returns the position of the cell at (row, col) in the cage map.
+    pub fn of_cell(row: usize, col: usize) -> Pos {
+        Pos { line: row + 1, col: col + 1 }
+    }
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.col)
+    }
+}
+
+/// Problems found by validating a puzzle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The cells of a cage are not orthogonally connected.
+    Disconnected(Pos),
+    /// A key is used for more than one (separate) cage in the cage map.
+    SharedKey(Pos, char),
+    /// There is more than one rule for a key.
+    DuplicateRule(Pos, char),
+    /// A rule refers to a key that does not appear in the cage map.
+    UnusedRule(Pos, char),
+    /// The goal can't be reached with the numbers of this puzzle size.
+    Unreachable(Pos, Op),
+}
+
+impl Diagnostic {
+    /// Returns the position in the `.ken` file the problem refers to.
+    pub fn pos(&self) -> Pos {
+        match *self {
+            Diagnostic::Disconnected(pos) | Diagnostic::SharedKey(pos, _) |
+            Diagnostic::DuplicateRule(pos, _) | Diagnostic::UnusedRule(pos, _) |
+            Diagnostic::Unreachable(pos, _) => pos,
+        }
+    }
+
+    /// Returns the description of the problem, without the position.
+    pub fn message(&self) -> String {
+        match *self {
+            Diagnostic::Disconnected(_) => "cage cells are not connected".into(),
+            Diagnostic::SharedKey(_, key) =>
+                format!("key ({}) is used for more than one cage", key),
+            Diagnostic::DuplicateRule(_, key) => format!("duplicate rule for cage ({})", key),
+            Diagnostic::UnusedRule(_, key) => format!("rule for missing cage ({})", key),
+            Diagnostic::Unreachable(_, op) =>
+                format!("goal {} can't be reached with this puzzle size", op),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.pos(), self.message())
+    }
+}
+
+/// Errors that can occur while loading, building or solving a puzzle.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the input failed.
+    Io(io::Error),
+    /// The puzzle size (length of the first map line) is not supported.  The
+    /// position is that of the first map line, if read from a `.ken` file.
+    InvalidSize(Option<Pos>, usize),
+    /// A line of the cage map has a different length than the first.
+    UnequalLineLength { pos: Pos, expected: usize, found: usize },
+    /// The input is not valid JSON.
+    InvalidJson(Pos, String),
+    /// The JSON input does not describe a puzzle.
+    InvalidSchema(String),
+    /// A header line contains an unknown option.
+    InvalidOption(Pos, String),
+    /// The variant declares a different number of digits than the size.  The
+    /// position is that of the `digits=` option, if read from a `.ken` file.
+    DigitCount { pos: Option<Pos>, size: usize, found: usize },
+    /// The Keen game ID is not valid.
+    InvalidGameId(&'static str),
+    /// A rule line is not of the form `key: goal op`.
+    InvalidRule(Pos),
+    /// The goal of a rule is not a valid number.
+    InvalidNumber(Pos, String),
+    /// The operator of a rule is not known.
+    InvalidOperator(Pos, String),
+    /// A cage in the map has no rule defining its goal.
+    MissingGoal(Pos, char),
+    /// The goal of a cage is not valid for its operation.
+    InvalidGoal(Pos, Op),
+    /// A cage has a number of cells not allowed for its operation, which
+    /// allows at most `max` cells.
+    CageSize { pos: Pos, op: Op, cells: usize, max: usize },
+    /// A cage contains a cell outside the puzzle.
+    CellOutside(Pos),
+    /// A cage contains a cell that already belongs to another cage.
+    CellTaken(Pos),
+    /// A region does not have as many cells as the puzzle size.
+    RegionSize { pos: Pos, expected: usize, found: usize },
+    /// A region contains a cell that already belongs to a region.
+    RegionOverlap(Pos),
+    /// The region map has fewer lines than the cage map.
+    MissingRegionLines(Pos),
+    /// A cage's operation can't be written in the requested format.
+    UnsupportedOp(Pos, Op),
+    /// The puzzle's variant rules can't be written in the requested format.
+    UnsupportedVariant(String),
+    /// A cell is not covered by any cage.
+    NotCovered(Pos),
+    /// Validating the puzzle found problems.
+    Invalid(Vec<Diagnostic>),
+    /// The puzzle has no solution.
+    NoSolution,
+    /// The puzzle has more than one solution.
+    MultipleSolutions,
+    /// The cage size weights for generating a puzzle are all zero.
+    ZeroCageWeights,
+    /// More cage size weights were given for generating a puzzle than the
+    /// largest allowed cage size.
+    TooManyCageWeights(usize),
+    /// The size is too large to generate a puzzle in reasonable time.
+    GenerationSize(usize),
+    /// No puzzle with a unique solution was found in the allowed tries.
+    GenerationFailed,
+}
+
+impl Error {
+    /// Returns the position in the `.ken` file the error refers to, if any.
+    pub fn pos(&self) -> Option<Pos> {
+        match *self {
+            Error::UnequalLineLength { pos, .. } | Error::CageSize { pos, .. } |
+            Error::RegionSize { pos, .. } | Error::RegionOverlap(pos) |
+            Error::MissingRegionLines(pos) |
+            Error::InvalidRule(pos) | Error::InvalidNumber(pos, _) | Error::InvalidJson(pos, _) |
+            Error::InvalidOption(pos, _) |
+            Error::InvalidOperator(pos, _) | Error::MissingGoal(pos, _) |
+            Error::InvalidGoal(pos, _) | Error::CellOutside(pos) | Error::CellTaken(pos) |
+            Error::UnsupportedOp(pos, _) | Error::NotCovered(pos) => Some(pos),
+            Error::InvalidSize(pos, _) | Error::DigitCount { pos, .. } => pos,
+            Error::Io(_) | Error::InvalidSchema(_) | Error::InvalidGameId(_) |
+            Error::UnsupportedVariant(_) |
+            Error::Invalid(_) | Error::NoSolution | Error::MultipleSolutions |
+            Error::ZeroCageWeights | Error::TooManyCageWeights(_) | Error::GenerationSize(_) | Error::GenerationFailed => None,
+        }
+    }
+
+    /// Returns the description of the error, without the position.
+    pub fn message(&self) -> String {
+        Message(self).to_string()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(pos) = self.pos() {
+            write!(f, "{}: ", pos)?;
+        }
+        write!(f, "{}", Message(self))
+    }
+}
+
+/// Formats the description of an error, without the position.
+struct Message<'a>(&'a Error);
+
+impl<'a> fmt::Display for Message<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::InvalidSize(_, size) =>
+                write!(f, "kenken size must be between 2 and {} (found {})", ::MAX_SIZE, size),
+            Error::UnequalLineLength { expected, found, .. } =>
+                write!(f, "unequal line lengths (expected {}, found {})", expected, found),
+            Error::InvalidJson(_, ref msg) => write!(f, "invalid JSON: {}", msg),
+            Error::InvalidSchema(ref msg) => write!(f, "invalid puzzle: {}", msg),
+            Error::InvalidOption(_, ref word) => write!(f, "unknown header option: {}", word),
+            Error::DigitCount { size, found, .. } =>
+                write!(f, "puzzle of size {} needs {} digits, not {}", size, size, found),
+            Error::InvalidGameId(msg) => write!(f, "invalid game ID: {}", msg),
+            Error::InvalidRule(_) => write!(f, "invalid line with cage rule"),
+            Error::InvalidNumber(_, ref num) => write!(f, "invalid number: {}", num),
+            Error::InvalidOperator(_, ref op) => write!(f, "invalid operator: {}", op),
+            Error::MissingGoal(_, key) => write!(f, "found cage ({}) without defined goal", key),
+            Error::InvalidGoal(_, op) => write!(f, "invalid goal for cage: {}", op),
+            Error::CageSize { op: Op::Const(_), cells, .. } =>
+                write!(f, "constant cages must have 1 cell, not {}", cells),
+            Error::CageSize { op: Op::Sub(_), cells, max: 2, .. } |
+            Error::CageSize { op: Op::Div(_), cells, max: 2, .. } if cells > 2 =>
+                write!(f, "sub/div cages must have 2 cells, not {} (unless the long-sub-div \
+                           variant is used)", cells),
+            Error::CageSize { op: Op::Mod(_), cells, .. } |
+            Error::CageSize { op: Op::Pow(_), cells, .. } =>
+                write!(f, "mod/power cages must have 2 cells, not {}", cells),
+            Error::CageSize { op: Op::Min(_), cells, max, .. } |
+            Error::CageSize { op: Op::Max(_), cells, max, .. } |
+            Error::CageSize { op: Op::Gcd(_), cells, max, .. } |
+            Error::CageSize { op: Op::Lcm(_), cells, max, .. } =>
+                write!(f, "min/max/gcd/lcm cages must have 2 to {} cells at this size, not {}",
+                       max, cells),
+            Error::CageSize { op: Op::Mul(0), cells, max, .. } |
+            Error::CageSize { op: Op::Div(0), cells, max, .. } if max > 2 =>
+                write!(f, "cages with a goal of zero must have 2 to {} cells at this size, not {}",
+                       max, cells),
+            Error::CageSize { op: Op::Sub(_), cells, max: 2, .. } |
+            Error::CageSize { op: Op::Div(_), cells, max: 2, .. } =>
+                write!(f, "sub/div cages must have 2 cells, not {}", cells),
+            Error::CageSize { op: Op::Unknown(_), cells, max, .. } =>
+                write!(f, "cages with hidden operation must have 2 to {} cells, not {}",
+                       max, cells),
+            Error::CageSize { cells, max, .. } =>
+                write!(f, "cages must have 2 to {} cells, not {}", max, cells),
+            Error::CellOutside(_) => write!(f, "cell is outside the puzzle"),
+            Error::CellTaken(_) => write!(f, "cell is already in another cage"),
+            Error::RegionSize { expected, found, .. } =>
+                write!(f, "regions must have {} cells, not {}", expected, found),
+            Error::RegionOverlap(_) => write!(f, "cell is already in another region"),
+            Error::MissingRegionLines(_) =>
+                write!(f, "region map must have as many lines as the cage map"),
+            Error::UnsupportedOp(_, op) =>
+                write!(f, "cage operation {} is not supported by this format", op),
+            Error::UnsupportedVariant(ref opts) =>
+                write!(f, "variant {} is not supported by this format", opts),
+            Error::NotCovered(_) => write!(f, "cell is not covered by any cage"),
+            Error::Invalid(ref diags) => {
+                for (i, diag) in diags.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { "; " } else { "" }, diag)?;
+                }
+                Ok(())
+            }
+            Error::NoSolution => write!(f, "found no solution"),
+            Error::MultipleSolutions => write!(f, "found more than 1 solution"),
+            Error::ZeroCageWeights => write!(f, "cage weights must not be all zero"),
+            Error::TooManyCageWeights(found) =>
+                write!(f, "cage weights can be given for up to {} cells (found {})",
+                       ::generator::MAX_GEN_CAGE_LEN, found),
+            Error::GenerationSize(size) =>
+                write!(f, "puzzles can be generated up to size {} (found {})",
+                       ::generator::MAX_GEN_SIZE, size),
+            Error::GenerationFailed => write!(f, "no uniquely solvable puzzle found"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,268 @@
+// KenKen puzzle solver, (c) 2016 Georg Brandl.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use {KenKen, Cage, Op, Error, Strategy};
+use search::search_bounded;
+use helpers::{Tbl, SmallVec, WideVec};
+
+/// The largest size that puzzles can be generated for.  Beyond that, nearly
+/// every candidate puzzle exceeds the search step limit, so that generating
+/// one takes many minutes or fails.
+pub const MAX_GEN_SIZE: usize = 20;
+
+/// The largest cage size that puzzles can be generated with.  Add and Mul
+/// cages with many more cells have too many combinations to check.
+pub const MAX_GEN_CAGE_LEN: usize = ::MAX_FILTER_CAGE_LEN;
+
+/// A simple xorshift* random number generator, good enough for making puzzles.
+pub struct Rng(u64);
+
+impl Rng {
+    /// Creates a generator from a seed (zero is replaced by a fixed constant).
+    pub fn new(seed: u64) -> Rng {
+        Rng(if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed })
+    }
+
+    /// Creates a generator seeded from the current time.
+    pub fn from_time() -> Rng {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Rng::new(now.as_secs() ^ (now.subsec_nanos() as u64) << 32)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a random number in 0..n.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Shuffles a slice in place.
+    pub fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in (1..v.len()).rev() {
+            let j = self.below(i + 1);
+            v.swap(i, j);
+        }
+    }
+
+    /// Returns an index into `weights`, chosen with probability proportional
+    /// to the weights.
+    pub fn weighted(&mut self, weights: &[u32]) -> usize {
+        let total = weights.iter().sum::<u32>() as usize;
+        let mut n = self.below(total) as u32;
+        for (i, &w) in weights.iter().enumerate() {
+            if n < w {
+                return i;
+            }
+            n -= w;
+        }
+        weights.len() - 1
+    }
+}
+
+/// Options for generating a puzzle.
+pub struct GenOptions {
+    /// Size of the puzzle.
+    pub size: usize,
+    /// Relative weights of cage sizes; element i is the weight of cages with
+    /// i+1 cells.  There can be at most `MAX_GEN_CAGE_LEN` weights.
+    pub cage_weights: Vec<u32>,
+    /// Maximum number of puzzles to try before giving up.
+    pub max_tries: u32,
+    /// Maximum number of search steps for checking that a puzzle has a
+    /// unique solution; puzzles that need more are skipped.
+    pub max_steps: u32,
+}
+
+impl GenOptions {
+    /// Returns default options for the given size.
+    pub fn new(size: usize) -> GenOptions {
+        GenOptions { size, cage_weights: vec![1, 8, 6, 2], max_tries: 1000, max_steps: 20000 }
+    }
+}
+
+/// Generates a random Latin square of given size.
+///
+/// The rows are filled one after another, each with a random matching of
+/// the columns to the numbers not used in them yet.  Such a matching always
+/// exists, so no backtracking is needed, and every Latin square can come up.
+fn latin_square(rng: &mut Rng, size: usize) -> Tbl<u32> {
+    let mut res = Tbl::square(size, 0);
+    // used[col][num] is true if the number is already in the column
+    let mut used = vec![vec![false; size]; size];
+    for row in 0..size {
+        // the column of each number in this row
+        let mut col_of = vec![!0; size];
+        let mut cols = (0..size).collect::<Vec<_>>();
+        rng.shuffle(&mut cols);
+        for col in cols {
+            augment(rng, &used, &mut col_of, &mut vec![false; size], col);
+        }
+        for (num, &col) in col_of.iter().enumerate() {
+            used[col][num] = true;
+            res.put(row, col, num as u32 + 1);
+        }
+    }
+    res
+}
+
+/// Finds a number for `col` in the matching, moving the column that has it
+/// to another number if needed (trying the numbers in random order).
+/// Returns false if there is no way to do that.
+fn augment(rng: &mut Rng, used: &[Vec<bool>], col_of: &mut [usize], seen: &mut [bool],
+           col: usize) -> bool {
+    let mut nums = (0..col_of.len()).filter(|&num| !used[col][num]).collect::<Vec<_>>();
+    rng.shuffle(&mut nums);
+    for num in nums {
+        if !seen[num] {
+            seen[num] = true;
+            if col_of[num] == !0 || augment(rng, used, col_of, seen, col_of[num]) {
+                col_of[num] = col;
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Partitions the square into orthogonally connected cages, with sizes
+/// chosen according to the weights.
+fn partition(rng: &mut Rng, size: usize, weights: &[u32]) -> Vec<Vec<(usize, usize)>> {
+    let mut owner = Tbl::square(size, false);
+    let mut order = (0..size*size).map(|i| (i / size, i % size)).collect::<Vec<_>>();
+    rng.shuffle(&mut order);
+    let mut cages = Vec::new();
+    for (row, col) in order {
+        if *owner.get(row, col) {
+            continue;
+        }
+        let target = rng.weighted(weights) + 1;
+        let mut cells = vec![(row, col)];
+        owner.put(row, col, true);
+        while cells.len() < target {
+            let mut free = Vec::new();
+            for &(r, c) in &cells {
+                if r > 0 && !owner.get(r - 1, c) { free.push((r - 1, c)); }
+                if c > 0 && !owner.get(r, c - 1) { free.push((r, c - 1)); }
+                if r < size - 1 && !owner.get(r + 1, c) { free.push((r + 1, c)); }
+                if c < size - 1 && !owner.get(r, c + 1) { free.push((r, c + 1)); }
+            }
+            if free.is_empty() {
+                break;
+            }
+            let (r, c) = free[rng.below(free.len())];
+            owner.put(r, c, true);
+            cells.push((r, c));
+        }
+        cells.sort();
+        cages.push(cells);
+    }
+    cages
+}
+
+/// Chooses an operation for a cage with the given values.
+fn choose_op(rng: &mut Rng, vals: &[i32]) -> Op {
+    if vals.len() == 1 {
+        return Op::Const(vals[0]);
+    }
+    let sum = vals.iter().sum();
+    let prod = vals.iter().try_fold(1i32, |p, &v| p.checked_mul(v));
+    if vals.len() == 2 {
+        let (hi, lo) = if vals[0] > vals[1] { (vals[0], vals[1]) } else { (vals[1], vals[0]) };
+        if hi % lo == 0 && rng.below(3) != 0 {
+            return Op::Div(hi / lo);
+        }
+        if rng.below(2) == 0 {
+            return Op::Sub(hi - lo);
+        }
+    }
+    match prod {
+        Some(prod) if rng.below(2) == 0 => Op::Mul(prod),
+        _ => Op::Add(sum),
+    }
+}
+
+/// Generates a random puzzle, which need not have a unique solution.
+fn generate_one(rng: &mut Rng, opts: &GenOptions) -> Result<KenKen, Error> {
+    let mut ken = KenKen::new(opts.size)?;
+    let square = latin_square(rng, opts.size);
+    for cells in partition(rng, opts.size, &opts.cage_weights) {
+        let vals = cells.iter().map(|&(row, col)| *square.get(row, col) as i32).collect::<Vec<_>>();
+        let op = choose_op(rng, &vals);
+        ken.add_cage(Cage::new(cells, op))?;
+    }
+    Ok(ken)
+}
+
+/// Generates a random puzzle with exactly one solution.  The size must be at
+/// most `MAX_GEN_SIZE`.
+pub fn generate(rng: &mut Rng, opts: &GenOptions) -> Result<KenKen, Error> {
+    if opts.cage_weights.iter().all(|&w| w == 0) {
+        return Err(Error::ZeroCageWeights);
+    }
+    if opts.cage_weights.len() > MAX_GEN_CAGE_LEN {
+        return Err(Error::TooManyCageWeights(opts.cage_weights.len()));
+    }
+    if opts.size > MAX_GEN_SIZE {
+        return Err(Error::GenerationSize(opts.size));
+    }
+    for _ in 0..opts.max_tries {
+        let ken = generate_one(rng, opts)?;
+        // the input order can take very long for large puzzles
+        let res = if ken.needs_wide() {
+            search_bounded::<WideVec>(&ken, Some(2), Strategy::MinRemaining, opts.max_steps)
+        } else {
+            search_bounded::<SmallVec>(&ken, Some(2), Strategy::MinRemaining, opts.max_steps)
+        };
+        if res.count == 1 && !res.limit_reached {
+            return Ok(ken);
+        }
+    }
+    Err(Error::GenerationFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latin_squares() {
+        let mut rng = Rng::new(1);
+        for &size in &[2, 3, 5, 9, 16, 63] {
+            let square = latin_square(&mut rng, size);
+            for i in 0..size {
+                let mut row = (0..size).map(|j| *square.get(i, j)).collect::<Vec<_>>();
+                let mut col = (0..size).map(|j| *square.get(j, i)).collect::<Vec<_>>();
+                row.sort();
+                col.sort();
+                assert_eq!(row, (1..size as u32 + 1).collect::<Vec<_>>());
+                assert_eq!(col, row);
+            }
+        }
+    }
+
+    #[test]
+    fn unique_puzzle() {
+        let ken = generate(&mut Rng::new(7), &GenOptions::new(5)).unwrap();
+        assert!(ken.solve().is_ok());
+        assert!(matches!(generate(&mut Rng::new(7), &GenOptions { size: 5, cage_weights: vec![0],
+                                                                   max_tries: 1,
+                                                                   max_steps: 1 }),
+                         Err(Error::ZeroCageWeights)));
+        assert!(matches!(generate(&mut Rng::new(7), &GenOptions::new(MAX_GEN_SIZE + 1)),
+                         Err(Error::GenerationSize(21))));
+        let mut weights = vec![0; 22];
+        weights[21] = 1;
+        assert!(matches!(generate(&mut Rng::new(7), &GenOptions { cage_weights: weights,
+                                                                   ..GenOptions::new(4) }),
+                         Err(Error::TooManyCageWeights(22))));
+        let weights = vec![0, 0, 0, 0, 0, 1];
+        assert!(generate(&mut Rng::new(7), &GenOptions { cage_weights: weights,
+                                                         ..GenOptions::new(6) }).is_ok());
+    }
+}
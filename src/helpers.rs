@@ -1,19 +1,25 @@
 // KenKen puzzle solver, (c) 2016 Georg Brandl.
 
 use std::fmt::{self, Write};
-use std::iter::repeat;
+use std::iter;
 
-use KenKen;
+use {KenKen, House, HouseKind};
 
 /// Represents a square sized table of some value type.
 #[derive(Clone)]
 pub struct Tbl<T>(usize, Vec<T>);
 
 impl<T> Tbl<T> {
+    /// Creates a new n*n table with all entries set to `t`.
     pub fn square(n: usize, t: T) -> Tbl<T> where T: Clone {
         Tbl(n, vec![t; n*n])
     }
 
+    /// Returns the length of a row or column.
+    pub fn size(&self) -> usize {
+        self.0
+    }
+
     pub fn get(&self, i: usize, j: usize) -> &T {
         &self.1[i*self.0 + j]
     }
@@ -26,32 +32,39 @@ impl<T> Tbl<T> {
         self.1[i*self.0 + j] = t;
     }
 
+    /// Returns the entries in row-major order.
     pub fn as_vec(&self) -> &Vec<T> {
         &self.1
     }
+
+    /// Returns a table with `f` applied to each entry.
+    pub fn map<U, F: Fn(&T) -> U>(&self, f: F) -> Tbl<U> {
+        Tbl(self.0, self.1.iter().map(f).collect())
+    }
 }
 
 /// Function to display a (finished) puzzle solution.
-impl fmt::Display for Tbl<u32> {
+impl<T: fmt::Display> fmt::Display for Tbl<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let sep = repeat("+---").take(self.0).collect::<String>() + "+\n";
+        let sep = "+---".repeat(self.0) + "+\n";
         for row in self.1.chunks(self.0) {
-            try!(f.write_str(&sep));
+            f.write_str(&sep)?;
             for cell in row {
-                try!(write!(f, "| {} ", cell));
+                write!(f, "| {} ", cell)?;
             }
-            try!(f.write_str("|\n"));
+            f.write_str("|\n")?;
         }
         f.write_str(&sep)
     }
 }
 
-/// Represents a set of values (32 bits means we can handle values 0...31).
+/// Represents a set of values (64 bits means we can handle values 0...63).
 ///
 /// Since the set is used for candidate numbers, and the puzzle size is
-/// restricted to 15, we don't need more space.
-#[derive(Clone, PartialEq)]
-pub struct BitSet(u32);
+/// restricted to 63, we don't need more space.  Negative numbers are stored
+/// shifted by `KenKen::offset`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BitSet(u64);
 
 impl BitSet {
     pub fn new_empty() -> BitSet {
@@ -59,7 +72,7 @@ impl BitSet {
     }
 
     pub fn new_full(size: usize) -> BitSet {
-        BitSet((1 << (size + 1)) - 2)
+        BitSet((!0 >> (63 - size)) & !1)
     }
 
     pub fn test(&self, bit: u32) -> bool {
@@ -83,79 +96,210 @@ impl BitSet {
     }
 
     pub fn get_two(&self) -> (u32, u32) {
-        (32 - self.0.leading_zeros() - 1, self.0.trailing_zeros())
+        (64 - self.0.leading_zeros() - 1, self.0.trailing_zeros())
+    }
+
+    /// Returns the smallest and largest value in the set, if it isn't empty.
+    pub fn bounds(&self) -> Option<(u32, u32)> {
+        if self.0 == 0 {
+            None
+        } else {
+            Some((self.0.trailing_zeros(), 63 - self.0.leading_zeros()))
+        }
+    }
+
+    /// Returns the set of values contained in either set.
+    pub fn union(&self, other: &BitSet) -> BitSet {
+        BitSet(self.0 | other.0)
+    }
+
+    /// Returns an iterator over the values in the set, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item=u32> {
+        let mut bits = self.0;
+        iter::from_fn(move || if bits == 0 { None } else {
+            let i = bits.trailing_zeros();
+            bits &= bits - 1;
+            Some(i)
+        })
     }
+}
+
+/// Characters used to display numbers as a single character (0 to 63).
+const NUM_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz@#";
 
-    pub fn to_string(&self) -> String {
+/// Displays the set as the concatenation of its numbers if they are all
+/// single digits, and as a comma-separated list otherwise.
+///
+/// With the alternate flag (`{:#}`), each number is displayed as a single
+/// character: 0-9, then A-Z for 10-35, a-z for 36-61, and @, # for 62, 63.
+impl fmt::Display for BitSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut res = String::new();
-        for i in 1..10 {
+        for i in 0..64 {
             if self.test(i) {
-                res.push_str(&format!("{}", i));
+                if f.alternate() {
+                    res.push(NUM_CHARS[i as usize] as char);
+                } else {
+                    if self.0 >> 10 != 0 && !res.is_empty() {
+                        res.push(',');
+                    }
+                    res.push_str(&i.to_string());
+                }
             }
         }
-        res
+        f.pad(&res)
     }
 }
 
-/// Represents a mask for numbers in rows and columns, used to check if we can insert
-/// a number in a certain cell.
-pub struct RowColMask(Vec<BitSet>, Vec<BitSet>);
+/// Represents a mask for the numbers still free in each house (row, column
+/// or region), used to check if we can insert a number in a certain cell.
+pub struct HouseMask {
+    rows: Vec<BitSet>,
+    cols: Vec<BitSet>,
+    /// Masks for the other houses (diagonals and regions), and their indices
+    /// for each cell; those of cell i are at `others[starts[i]..starts[i+1]]`.
+    /// Rows and columns are kept separately since they are always there.
+    masks: Vec<BitSet>,
+    others: Vec<usize>,
+    starts: Vec<usize>,
+}
+
+impl HouseMask {
+    /// Creates a mask for the houses of a puzzle, which uses the numbers in
+    /// `digits`.
+    pub fn new(size: usize, digits: &BitSet, houses: &[House]) -> HouseMask {
+        let others = houses.iter().filter(|house| {
+            house.kind != HouseKind::Row && house.kind != HouseKind::Column
+        }).collect::<Vec<_>>();
+        let mut of_cell = vec![Vec::new(); size * size];
+        for (i, house) in others.iter().enumerate() {
+            for &(row, col) in &house.cells {
+                of_cell[row * size + col].push(i);
+            }
+        }
+        let mut starts = vec![0];
+        for idxs in &of_cell {
+            starts.push(starts[starts.len() - 1] + idxs.len());
+        }
+        HouseMask {
+            rows: vec![digits.clone(); size],
+            cols: vec![digits.clone(); size],
+            masks: vec![digits.clone(); others.len()],
+            others: of_cell.concat(),
+            starts,
+        }
+    }
 
-impl RowColMask {
-    pub fn new(size: usize) -> RowColMask {
-        RowColMask(vec![BitSet::new_full(size); size], vec![BitSet::new_full(size); size])
+    /// Returns the indices of the other houses of the cell at (row, col).
+    fn others(&self, row: usize, col: usize) -> ::std::ops::Range<usize> {
+        let i = row * self.rows.len() + col;
+        self.starts[i]..self.starts[i + 1]
     }
 
     pub fn ok(&self, row: usize, col: usize, el: u32) -> bool {
-        self.0[row].test(el) && self.1[col].test(el)
+        self.rows[row].test(el) && self.cols[col].test(el) && (self.masks.is_empty() ||
+            self.others(row, col).all(|i| self.masks[self.others[i]].test(el)))
     }
 
     pub fn set(&mut self, row: usize, col: usize, el: u32) {
-        self.0[row].set(el);
-        self.1[col].set(el);
+        self.rows[row].set(el);
+        self.cols[col].set(el);
+        for i in self.others(row, col) {
+            self.masks[self.others[i]].set(el);
+        }
     }
 
     pub fn clear(&mut self, row: usize, col: usize, el: u32) {
-        self.0[row].clear(el);
-        self.1[col].clear(el);
+        self.rows[row].clear(el);
+        self.cols[col].clear(el);
+        for i in self.others(row, col) {
+            self.masks[self.others[i]].clear(el);
+        }
     }
 }
 
-/// A small vector of up to 15 4-bit elements, represented as an u64.  The last
-/// 4 bits hold the number of elements.
+/// Common interface of small vectors of numbers packed into a single integer.
 ///
-/// This is used to hold candidate sequences for cages.  This means we can have
-/// cages with up to 15 cells, and the numbers must be <= 15 too.
-#[derive(Clone)]
-pub struct SmallVec(u64);
-
-impl SmallVec {
-    pub fn new_with(n: u32) -> SmallVec {
-        SmallVec((1 << 60) | (n as u64))
+/// This is used to hold candidate sequences for cages.  The smaller types are
+/// faster, so the solver picks the smallest type that can hold the puzzle's
+/// numbers and cages.  Like in `BitSet`, negative numbers are stored shifted
+/// by `KenKen::offset`.
+pub trait CandVec: Clone + Ord {
+    /// Maximum number of elements (and therefore cells in a cage).
+    const MAX_LEN: usize;
+    /// Maximum value of an element (and therefore size of the puzzle).
+    const MAX_VAL: u32;
+
+    fn new_with(n: u32) -> Self;
+    fn new_with_two(n1: u32, n2: u32) -> Self;
+    fn get(&self, ix: usize) -> u32;
+    fn len(&self) -> usize;
+    fn push(&mut self, n: u32);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    pub fn new_with_two(n1: u32, n2: u32) -> SmallVec {
-        SmallVec((2 << 60) | (n1 as u64) | (n2 as u64) << 4)
+    fn iter(&self) -> CandVecIter<Self> {
+        CandVecIter(self.clone(), self.len(), 0)
     }
+}
 
-    pub fn get(&self, ix: usize) -> u32 {
-        (self.0 >> (ix << 2)) as u32 & 0xf
-    }
+macro_rules! packed_vec {
+    ($(#[$attr:meta])* $name:ident, $int:ty, $bits:expr, $maxlen:expr) => {
+        $(#[$attr])*
+        #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+        pub struct $name($int);
 
-    pub fn push(&mut self, n: u32) {
-        let len = self.0 >> 60;
-        let shift = len << 2;
-        self.0 = (self.0 & ((1 << shift) - 1)) | (n as u64) << shift | (len + 1) << 60;
-    }
+        impl CandVec for $name {
+            const MAX_LEN: usize = $maxlen;
+            const MAX_VAL: u32 = (1 << $bits) - 1;
+
+            fn new_with(n: u32) -> $name {
+                $name((1 << ($maxlen * $bits)) | (n as $int))
+            }
+
+            fn new_with_two(n1: u32, n2: u32) -> $name {
+                $name((2 << ($maxlen * $bits)) | (n1 as $int) | (n2 as $int) << $bits)
+            }
+
+            fn get(&self, ix: usize) -> u32 {
+                (self.0 >> (ix * $bits)) as u32 & ((1 << $bits) - 1)
+            }
 
-    pub fn iter(&self) -> SmallVecIter {
-        SmallVecIter(self.clone(), (self.0 >> 60) as usize, 0)
+            fn len(&self) -> usize {
+                (self.0 >> ($maxlen * $bits)) as usize
+            }
+
+            fn push(&mut self, n: u32) {
+                let len = self.0 >> ($maxlen * $bits);
+                let shift = len * $bits;
+                self.0 = (self.0 & ((1 << shift) - 1)) | (n as $int) << shift
+                    | (len + 1) << ($maxlen * $bits);
+            }
+        }
     }
 }
 
-pub struct SmallVecIter(SmallVec, usize, usize);
-
-impl Iterator for SmallVecIter {
+packed_vec!(
+    /// A small vector of up to 15 4-bit elements, represented as an u64.  The last
+    /// 4 bits hold the number of elements.
+    ///
+    /// This means we can have cages with up to 15 cells, and the numbers must be
+    /// <= 15 too.
+    SmallVec, u64, 4, 15);
+
+packed_vec!(
+    /// A small vector of up to 20 6-bit elements, represented as an u128.  The
+    /// last 8 bits hold the number of elements.
+    ///
+    /// This means we can have cages with up to 20 cells, and the numbers must be
+    /// <= 63 too.
+    WideVec, u128, 6, 20);
+
+pub struct CandVecIter<V>(V, usize, usize);
+
+impl<V: CandVec> Iterator for CandVecIter<V> {
     type Item = u32;
 
     fn next(&mut self) -> Option<u32> {
@@ -168,12 +312,41 @@ impl Iterator for SmallVecIter {
     }
 }
 
+/// Splits a list of cells into groups of orthogonally connected cells.
+///
+/// The groups are returned in the order of their first cell in the list.
+pub fn components(cells: &[(usize, usize)]) -> Vec<Vec<(usize, usize)>> {
+    let mut rest = cells.to_vec();
+    let mut res = Vec::new();
+    while !rest.is_empty() {
+        let mut group = vec![rest.remove(0)];
+        let mut i = 0;
+        while i < group.len() {
+            let (row, col) = group[i];
+            let mut j = 0;
+            while j < rest.len() {
+                let (r, c) = rest[j];
+                if (r == row && (c + 1 == col || col + 1 == c)) ||
+                   (c == col && (r + 1 == row || row + 1 == r)) {
+                    group.push(rest.remove(j));
+                } else {
+                    j += 1;
+                }
+            }
+            i += 1;
+        }
+        res.push(group);
+    }
+    res
+}
 
+/// Formats a square with the cage outlines of the puzzle, and `contents`
+/// (in row-major order) centered in the cells.
 pub fn format_square<T: fmt::Display>(ken: &KenKen, cellsize: usize, contents: &[T]) -> String {
     let mut res = String::with_capacity((cellsize + 1) * (ken.size + 2));
     let max = ken.size - 1;
     let cn = |i, j| if i <= max && j <= max { ken.cell2cage.get(i, j).0 } else { !0 };
-    let cs = |s| repeat(s).take(cellsize).collect::<String>();
+    let cs = |s: char| s.to_string().repeat(cellsize);
     res.push('┏');
     for j in 0..ken.size {
         res.push_str(&cs('━'));
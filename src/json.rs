@@ -0,0 +1,697 @@
+// KenKen puzzle solver, (c) 2016 Georg Brandl.
+
+//! Reading and writing puzzles and solve results as JSON.
+//!
+//! A puzzle is an object with the size and a list of cages; cells are given
+//! as 0-based `[row, col]` pairs, and `op` is one of `const`, `add`, `sub`,
+//! `mul`, `div`, `unknown` (for a hidden operation), `mod`, `pow`, `min`,
+//! `max`, `gcd`, `lcm` or `concat`:
+//!
+//! ```text
+//! {"size": 2, "cages": [{"cells": [[0, 0], [0, 1]], "op": "sub", "goal": 1},
+//!                       {"cells": [[1, 0]], "op": "const", "goal": 2},
+//!                       {"cells": [[1, 1]], "op": "const", "goal": 1}]}
+//! ```
+//!
+//! Variant rules are given as a list of the `.ken` header options, e.g.
+//! `"variant": ["long-sub-div"]`, and regions as lists of cells, e.g.
+//! `"regions": [[[0, 0], [1, 0]], [[0, 1], [1, 1]]]`.
+//!
+//! A solve result has a `status` (`solved`, `multiple`, `unsolvable` or
+//! `error`), the solution grids as lists of rows, the solution count, whether
+//! the search limit was reached, the number of search steps and the time in
+//! milliseconds.  Errors have a `message`, the `line` and `column` if the
+//! JSON text is invalid, and the `[row, col]` `cell` if the error refers to a
+//! cell.  Validation problems are given as a list of `problems`, each with a
+//! `message` and a `cell`.
+
+use std::fmt;
+
+use {KenKen, Cage, Op, Error, Diagnostic, Pos, Variant};
+use helpers::Tbl;
+use rating::Rating;
+use search::Solutions;
+
+/// A JSON value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Arr(Vec<Json>),
+    /// An object, with its members in the original order.
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parses a JSON document.
+    pub fn parse(text: &str) -> Result<Json, Error> {
+        let mut parser = Parser { chars: text.chars().collect(), idx: 0 };
+        let value = parser.value(0)?;
+        parser.skip_ws();
+        if parser.idx < parser.chars.len() {
+            return Err(parser.error("unexpected data after value"));
+        }
+        Ok(value)
+    }
+
+    /// Returns the member with the given key, if this is an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Obj(ref members) => members.iter().find(|m| m.0 == key).map(|m| &m.1),
+            _ => None,
+        }
+    }
+
+    /// Sets the member with the given key, if this is an object.
+    pub fn set(&mut self, key: &str, value: Json) {
+        if let Json::Obj(ref mut members) = *self {
+            match members.iter_mut().find(|m| m.0 == key) {
+                Some(member) => member.1 = value,
+                None => members.push((key.into(), value)),
+            }
+        }
+    }
+
+    /// Returns the value as an integer, if it is one.
+    pub fn as_i32(&self) -> Option<i32> {
+        match *self {
+            Json::Num(n) if n >= i32::MIN as f64 && n <= i32::MAX as f64 && n.fract() == 0. =>
+                Some(n as i32),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an unsigned integer, if it is one.
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self {
+            Json::Num(n) if n >= 0. && n <= u32::MAX as f64 && n.fract() == 0. => Some(n as u32),
+            _ => None,
+        }
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{}", ch)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Returns true if the characters form a number in the JSON grammar: no
+/// leading zeros, and digits after the `.` and the exponent.  (`f64::from_str`
+/// alone also accepts `01`, `1.` and `.5`.)
+fn is_number(mut cs: &[char]) -> bool {
+    fn digits(cs: &mut &[char]) -> usize {
+        let n = cs.iter().take_while(|ch| ch.is_ascii_digit()).count();
+        *cs = &cs[n..];
+        n
+    }
+    if cs.first() == Some(&'-') {
+        cs = &cs[1..];
+    }
+    let leading_zero = cs.first() == Some(&'0');
+    match digits(&mut cs) {
+        0 => return false,
+        n if n > 1 && leading_zero => return false,
+        _ => {}
+    }
+    if cs.first() == Some(&'.') {
+        cs = &cs[1..];
+        if digits(&mut cs) == 0 {
+            return false;
+        }
+    }
+    if let Some('e') | Some('E') = cs.first() {
+        cs = &cs[1..];
+        if let Some('+') | Some('-') = cs.first() {
+            cs = &cs[1..];
+        }
+        if digits(&mut cs) == 0 {
+            return false;
+        }
+    }
+    cs.is_empty()
+}
+
+/// Writes the value as compact JSON.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Num(n) if !n.is_finite() => write!(f, "null"),
+            Json::Num(n) if n.fract() == 0. && n.abs() < 1e15 => write!(f, "{}", n as i64),
+            Json::Num(n) => write!(f, "{}", n),
+            Json::Str(ref s) => write_str(f, s),
+            Json::Arr(ref items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(f, "{}{}", if i > 0 { ", " } else { "" }, item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Obj(ref members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    write!(f, "{}", if i > 0 { ", " } else { "" })?;
+                    write_str(f, key)?;
+                    write!(f, ": {}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// How deep arrays and objects may be nested.
+const MAX_DEPTH: usize = 128;
+
+/// A simple recursive descent JSON parser.
+struct Parser {
+    chars: Vec<char>,
+    idx: usize,
+}
+
+impl Parser {
+    /// Returns an error at the current position.
+    fn error(&self, msg: &str) -> Error {
+        let before = &self.chars[..self.idx];
+        let line = before.iter().filter(|&&ch| ch == '\n').count() + 1;
+        let col = before.iter().rev().take_while(|&&ch| ch != '\n').count() + 1;
+        Error::InvalidJson(Pos { line, col }, msg.into())
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.idx).cloned()
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.peek() {
+            self.idx += 1;
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), Error> {
+        for ch in word.chars() {
+            if self.peek() != Some(ch) {
+                return Err(self.error(&format!("expected {}", word)));
+            }
+            self.idx += 1;
+        }
+        Ok(())
+    }
+
+    /// Parses a value, nested in `depth` arrays or objects.
+    fn value(&mut self, depth: usize) -> Result<Json, Error> {
+        self.skip_ws();
+        if depth >= MAX_DEPTH && (self.peek() == Some('[') || self.peek() == Some('{')) {
+            return Err(self.error("nesting too deep"));
+        }
+        match self.peek() {
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.string().map(Json::Str),
+            Some('[') => {
+                self.idx += 1;
+                let mut items = Vec::new();
+                self.skip_ws();
+                if self.peek() == Some(']') {
+                    self.idx += 1;
+                    return Ok(Json::Arr(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(',') => self.idx += 1,
+                        Some(']') => { self.idx += 1; return Ok(Json::Arr(items)); }
+                        _ => return Err(self.error("expected , or ]")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.idx += 1;
+                let mut members = Vec::new();
+                self.skip_ws();
+                if self.peek() == Some('}') {
+                    self.idx += 1;
+                    return Ok(Json::Obj(members));
+                }
+                loop {
+                    self.skip_ws();
+                    if self.peek() != Some('"') {
+                        return Err(self.error("expected string"));
+                    }
+                    let key = self.string()?;
+                    self.skip_ws();
+                    self.expect(":")?;
+                    members.push((key, self.value(depth + 1)?));
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(',') => self.idx += 1,
+                        Some('}') => { self.idx += 1; return Ok(Json::Obj(members)); }
+                        _ => return Err(self.error("expected , or }")),
+                    }
+                }
+            }
+            Some(ch) if ch == '-' || ch.is_ascii_digit() => {
+                let start = self.idx;
+                while let Some(ch) = self.peek() {
+                    if !(ch.is_ascii_digit() || "+-.eE".contains(ch)) {
+                        break;
+                    }
+                    self.idx += 1;
+                }
+                let num = &self.chars[start..self.idx];
+                match num.iter().collect::<String>().parse() {
+                    Ok(n) if is_number(num) => Ok(Json::Num(n)),
+                    _ => {
+                        self.idx = start;
+                        Err(self.error("invalid number"))
+                    }
+                }
+            }
+            _ => Err(self.error("expected value")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        // from_str_radix alone would also accept a sign
+        let hex = self.chars.get(self.idx..self.idx + 4)
+                            .filter(|cs| cs.iter().all(char::is_ascii_hexdigit))
+                            .map(|cs| cs.iter().collect::<String>()).unwrap_or_default();
+        let code = u32::from_str_radix(&hex, 16).map_err(|_| self.error("invalid escape"))?;
+        self.idx += 4;
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.idx += 1;
+        let mut res = String::new();
+        loop {
+            let ch = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+            self.idx += 1;
+            match ch {
+                '"' => return Ok(res),
+                '\\' => {
+                    let esc = self.peek().ok_or_else(|| self.error("unterminated string"))?;
+                    self.idx += 1;
+                    res.push(match esc {
+                        '"' | '\\' | '/' => esc,
+                        'b' => '\x08',
+                        'f' => '\x0c',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            if (0xD800..0xDC00).contains(&code) {
+                                // a high surrogate needs a low one after it
+                                self.expect("\\u")?;
+                                let low = self.hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error("invalid escape"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            ::std::char::from_u32(code).ok_or_else(|| self.error("invalid escape"))?
+                        }
+                        _ => { self.idx -= 1; return Err(self.error("invalid escape")); }
+                    });
+                }
+                ch => res.push(ch),
+            }
+        }
+    }
+}
+
+fn num(n: usize) -> Json {
+    Json::Num(n as f64)
+}
+
+fn schema_error(what: &str) -> Error {
+    Error::InvalidSchema(what.into())
+}
+
+fn cells_to_json(cells: &[(usize, usize)]) -> Vec<Json> {
+    cells.iter().map(|&(row, col)| Json::Arr(vec![num(row), num(col)])).collect()
+}
+
+/// Reads a list of `[row, col]` cells.
+fn cells_from_json(json: Option<&Json>) -> Option<Vec<(usize, usize)>> {
+    match json {
+        Some(Json::Arr(cells)) => cells.iter().map(|cell| match *cell {
+            Json::Arr(ref rc) if rc.len() == 2 => match (rc[0].as_u32(), rc[1].as_u32()) {
+                (Some(row), Some(col)) => Some((row as usize, col as usize)),
+                _ => None,
+            },
+            _ => None,
+        }).collect(),
+        _ => None,
+    }
+}
+
+/// Converts a puzzle to JSON.
+pub fn puzzle_to_json(ken: &KenKen) -> Json {
+    let cages = ken.cages().iter().map(|cage| {
+        let op = match cage.operation() {
+            Op::Const(_) => "const",
+            Op::Add(_) => "add",
+            Op::Sub(_) => "sub",
+            Op::Mul(_) => "mul",
+            Op::Div(_) => "div",
+            Op::Unknown(_) => "unknown",
+            Op::Mod(_) => "mod",
+            Op::Pow(_) => "pow",
+            Op::Min(_) => "min",
+            Op::Max(_) => "max",
+            Op::Gcd(_) => "gcd",
+            Op::Lcm(_) => "lcm",
+            Op::Concat(_) => "concat",
+        };
+        let goal = cage.operation().goal();
+        Json::Obj(vec![("cells".into(), Json::Arr(cells_to_json(cage.cells()))),
+                       ("op".into(), Json::Str(op.into())),
+                       ("goal".into(), Json::Num(goal as f64))])
+    }).collect();
+    let mut res = Json::Obj(vec![("size".into(), num(ken.size())),
+                                 ("cages".into(), Json::Arr(cages))]);
+    if !ken.variant().is_standard() {
+        res.set("variant", Json::Arr(ken.variant().to_string().split(' ')
+                                        .map(|opt| Json::Str(opt.into())).collect()));
+    }
+    let regions = ken.regions().map(|region| Json::Arr(cells_to_json(&region.cells)))
+                               .collect::<Vec<_>>();
+    if !regions.is_empty() {
+        res.set("regions", Json::Arr(regions));
+    }
+    res
+}
+
+/// Builds a puzzle from its JSON representation, and validates it.
+pub fn puzzle_from_json(json: &Json) -> Result<KenKen, Error> {
+    let size = json.get("size").and_then(Json::as_u32)
+                               .ok_or_else(|| schema_error("size: expected a number"))?;
+    let mut variant = Variant::default();
+    match json.get("variant") {
+        None => (),
+        Some(Json::Arr(opts)) => for opt in opts {
+            match *opt {
+                Json::Str(ref opt) if variant.set_option(opt) => (),
+                _ => return Err(schema_error("variant: unknown option")),
+            }
+        },
+        _ => return Err(schema_error("variant: expected a list")),
+    }
+    let mut ken = KenKen::with_variant(size as usize, variant)?;
+    let cages = match json.get("cages") {
+        Some(Json::Arr(cages)) => cages,
+        _ => return Err(schema_error("cages: expected a list")),
+    };
+    for (i, cage) in cages.iter().enumerate() {
+        let goal = cage.get("goal").and_then(Json::as_i32).ok_or_else(|| {
+            schema_error(&format!("cages[{}].goal: expected a number", i))
+        })?;
+        let op = match cage.get("op") {
+            Some(Json::Str(op)) => match &**op {
+                "const" => Op::Const(goal),
+                "add" => Op::Add(goal),
+                "sub" => Op::Sub(goal),
+                "mul" => Op::Mul(goal),
+                "div" => Op::Div(goal),
+                "unknown" => Op::Unknown(goal),
+                "mod" => Op::Mod(goal),
+                "pow" => Op::Pow(goal),
+                "min" => Op::Min(goal),
+                "max" => Op::Max(goal),
+                "gcd" => Op::Gcd(goal),
+                "lcm" => Op::Lcm(goal),
+                "concat" => Op::Concat(goal),
+                _ => return Err(schema_error(&format!("cages[{}].op: unknown operation {}", i, op))),
+            },
+            _ => return Err(schema_error(&format!("cages[{}].op: expected a string", i))),
+        };
+        let cells = cells_from_json(cage.get("cells")).ok_or_else(|| {
+            schema_error(&format!("cages[{}].cells: expected a list of [row, col]", i))
+        })?;
+        if cells.is_empty() {
+            return Err(schema_error(&format!("cages[{}].cells: expected at least one cell", i)));
+        }
+        ken.add_cage(Cage::new(cells, op))?;
+    }
+    match json.get("regions") {
+        None => (),
+        Some(Json::Arr(regions)) => for (i, region) in regions.iter().enumerate() {
+            let cells = cells_from_json(Some(region)).ok_or_else(|| {
+                schema_error(&format!("regions[{}]: expected a list of [row, col]", i))
+            })?;
+            if cells.is_empty() {
+                return Err(schema_error(&format!("regions[{}]: expected at least one cell", i)));
+            }
+            ken.add_region(cells)?;
+        },
+        _ => return Err(schema_error("regions: expected a list")),
+    }
+    let diags = ken.validate();
+    if !diags.is_empty() {
+        return Err(Error::Invalid(diags));
+    }
+    Ok(ken)
+}
+
+/// Parses a puzzle from a JSON string.
+pub fn read_puzzle(text: &str) -> Result<KenKen, Error> {
+    puzzle_from_json(&Json::parse(text)?)
+}
+
+fn grid_to_json(grid: &Tbl<i32>) -> Json {
+    Json::Arr(grid.as_vec().chunks(grid.size()).map(|row| {
+        Json::Arr(row.iter().map(|&v| Json::Num(v as f64)).collect())
+    }).collect())
+}
+
+/// Converts a search result to JSON.  If `unique` is true, more than one
+/// solution is reported as status `multiple`.
+pub fn solutions_to_json(res: &Solutions, unique: bool, millis: f64) -> Json {
+    let status = if res.count == 0 {
+        "unsolvable"
+    } else if unique && res.count > 1 {
+        "multiple"
+    } else {
+        "solved"
+    };
+    Json::Obj(vec![("status".into(), Json::Str(status.into())),
+                   ("solutions".into(), Json::Arr(res.solutions.iter().map(grid_to_json).collect())),
+                   ("count".into(), num(res.count)),
+                   ("limit_reached".into(), Json::Bool(res.limit_reached)),
+                   ("steps".into(), Json::Num(res.steps as f64)),
+                   ("time_ms".into(), Json::Num(millis))])
+}
+
+/// Converts an error to JSON, with status `error`.  Cells are given as
+/// `[row, col]`, and validation problems as a list of `problems`.
+pub fn error_to_json(err: &Error) -> Json {
+    // positions in the message would refer to a `.ken` file
+    let message = match *err {
+        Error::Invalid(ref diags) =>
+            diags.iter().map(Diagnostic::message).collect::<Vec<_>>().join("; "),
+        _ => err.message(),
+    };
+    let mut res = Json::Obj(vec![("status".into(), Json::Str("error".into())),
+                                 ("message".into(), Json::Str(message))]);
+    match *err {
+        Error::InvalidJson(pos, _) => {
+            res.set("line", num(pos.line));
+            res.set("column", num(pos.col));
+        }
+        Error::CageSize { pos, .. } | Error::InvalidGoal(pos, _) | Error::CellOutside(pos) |
+        Error::CellTaken(pos) | Error::RegionSize { pos, .. } | Error::RegionOverlap(pos) |
+        Error::UnsupportedOp(pos, _) | Error::NotCovered(pos) => res.set("cell", cell_of(pos)),
+        Error::Invalid(ref diags) => {
+            res.set("problems", Json::Arr(diags.iter().map(|diag| {
+                Json::Obj(vec![("message".into(), Json::Str(diag.message())),
+                               ("cell".into(), cell_of(diag.pos()))])
+            }).collect()));
+        }
+        _ => (),
+    }
+    res
+}
+
+/// Returns the `[row, col]` cell of a position in the cage map.  Puzzles read
+/// from JSON have no header lines, so this is just the 0-based line and column.
+fn cell_of(pos: Pos) -> Json {
+    Json::Arr(vec![num(pos.line - 1), num(pos.col - 1)])
+}
+
+/// Converts a difficulty rating to JSON.
+pub fn rating_to_json(rating: &Rating) -> Json {
+    Json::Obj(vec![("grade".into(), Json::Str(rating.grade.to_string())),
+                   ("score".into(), Json::Num(rating.score as f64)),
+                   ("rounds".into(), Json::Obj(rating.rounds.iter().map(|&(name, rounds)| {
+                       (name.into(), num(rounds as usize))
+                   }).collect())),
+                   ("solved_by_deduction".into(), Json::Bool(rating.solved_by_deduction))])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema_message(text: &str) -> String {
+        match read_puzzle(text) {
+            Err(Error::InvalidSchema(msg)) => msg,
+            res => panic!("expected a schema error, got {:?}", res.map(|ken| ken.to_string())),
+        }
+    }
+
+    #[test]
+    fn puzzle_round_trip() {
+        for text in &["aabcc\nddbef\ngghef\niih4j\nkkllj\n\na: 3+\nb: 2-\nc: 10*\nd: 2/\n\
+                       e: 8?\nf: 1%\ng: 8^\nh: 2min\ni: 5max\nj: 2gcd\nk: 12lcm\nl: 45||\n",
+                      "!long-sub-div digits=-12,-1,10,12\nabcd\neeff\nghhh\ngijk\n\na: 10\n\
+                       b: 12\nc: -1\nd: -12\ne: -13+\nf: 22+\ng: -120*\nh: 11-\ni: 10\nj: 12\n\
+                       k: -1\n",
+                      "!diagonals\naab1\ncdbe\ncd2e\n3ffe\n\na: 5+\nb: 7+\nc: 4+\nd: 3*\n\
+                       e: 8*\nf: 3-\n\naabb\naabb\nccdd\nccdd\n"] {
+            let ken = text.parse::<KenKen>().unwrap();
+            let json = puzzle_to_json(&ken).to_string();
+            assert!(read_puzzle(&json).unwrap() == ken);
+            assert_eq!(Json::parse(&json).unwrap(), puzzle_to_json(&ken));
+        }
+    }
+
+    #[test]
+    fn strings() {
+        let json = Json::parse(r#""a\"b\\c\/d\b\f\n\r\té😀""#).unwrap();
+        assert_eq!(json, Json::Str("a\"b\\c/d\x08\x0c\n\r\té\u{1f600}".into()));
+        let text = Json::Str("\"\\\n\r\t\x01é".into()).to_string();
+        assert_eq!(text, r#""\"\\\n\r\t\u0001é""#);
+        assert_eq!(Json::parse(&text).unwrap(), Json::Str("\"\\\n\r\t\x01é".into()));
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#).unwrap(), Json::Str("\u{1f600}".into()));
+        for bad in &[r#""abc"#, r#""\x""#, r#""\u12g4""#, r#""\u+041""#, r#""\u12""#,
+                     r#""\ud83d""#, r#""\ud83d\u0041""#, r#""\ud83d\ud83d""#] {
+            assert!(matches!(Json::parse(bad), Err(Error::InvalidJson(..))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        let json = Json::parse("[0, -12, 3.5, 1e3, -2E-2, 2147483648]").unwrap();
+        assert_eq!(json, Json::Arr(vec![Json::Num(0.), Json::Num(-12.), Json::Num(3.5),
+                                        Json::Num(1000.), Json::Num(-0.02),
+                                        Json::Num(2147483648.)]));
+        if let Json::Arr(ref nums) = json {
+            assert_eq!(nums.iter().map(Json::as_i32).collect::<Vec<_>>(),
+                       [Some(0), Some(-12), None, Some(1000), None, None]);
+            assert_eq!(nums.iter().map(Json::as_u32).collect::<Vec<_>>(),
+                       [Some(0), None, None, Some(1000), None, Some(2147483648)]);
+        }
+        assert_eq!(json.to_string(), "[0, -12, 3.5, 1000, -0.02, 2147483648]");
+        for bad in &["-", "1.2.3", "1e", "01", "-00", "1.", "-.5", "1.e3", "1e+", "1-2", "[1,]",
+                     "{\"a\" 1}", "1 2"] {
+            assert!(matches!(Json::parse(bad), Err(Error::InvalidJson(..))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn nesting() {
+        let ok = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(Json::parse(&ok).is_ok());
+        let nested = format!("{}{}", "[".repeat(200000), "]".repeat(200000));
+        match Json::parse(&nested) {
+            Err(Error::InvalidJson(pos, msg)) => {
+                assert_eq!(pos, Pos { line: 1, col: MAX_DEPTH + 1 });
+                assert_eq!(msg, "nesting too deep");
+            }
+            res => panic!("expected a JSON error, got {:?}", res),
+        }
+        match Json::parse(&"{\"a\": ".repeat(MAX_DEPTH + 1)) {
+            Err(Error::InvalidJson(_, msg)) => assert_eq!(msg, "nesting too deep"),
+            res => panic!("expected a JSON error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn error_position() {
+        match Json::parse("{\"size\": 4,\n \"cages\": [}") {
+            Err(Error::InvalidJson(pos, _)) => assert_eq!(pos, Pos { line: 2, col: 12 }),
+            res => panic!("expected a JSON error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn schema_errors() {
+        assert!(schema_message(r#"{"cages": []}"#).starts_with("size:"));
+        assert!(schema_message(r#"{"size": "4", "cages": []}"#).starts_with("size:"));
+        assert!(schema_message(r#"{"size": 4}"#).starts_with("cages:"));
+        assert!(schema_message(r#"{"size": 4, "cages": [], "variant": ["x"]}"#)
+                .starts_with("variant:"));
+        assert!(schema_message(r#"{"size": 2, "cages": [{"cells": [[0, 0], [0, 1]],
+                                   "op": "xor", "goal": 3}]}"#)
+                .starts_with("cages[0].op: unknown operation xor"));
+        assert!(schema_message(r#"{"size": 2, "cages": [{"cells": [[0, 0], [0, 1]],
+                                   "goal": 3}]}"#).starts_with("cages[0].op:"));
+        assert!(schema_message(r#"{"size": 2, "cages": [{"cells": [[0, 0], [0, 1]],
+                                   "op": "add", "goal": 1.5}]}"#)
+                .starts_with("cages[0].goal:"));
+        for cells in &["[[0, 0], [0]]", "[[0, -1]]", "[0, 1]", "\"a1\""] {
+            let text = format!(r#"{{"size": 2, "cages": [{{"cells": {}, "op": "add",
+                                    "goal": 3}}]}}"#, cells);
+            assert!(schema_message(&text).starts_with("cages[0].cells:"), "{}", cells);
+        }
+        assert!(schema_message(r#"{"size": 2, "cages": [], "regions": [[[0]]]}"#)
+                .starts_with("regions[0]:"));
+        for size in &[0, 1, 64] {
+            let text = format!(r#"{{"size": {}, "cages": []}}"#, size);
+            assert!(matches!(read_puzzle(&text), Err(Error::InvalidSize(None, _))), "{}",
+                    size);
+        }
+        assert!(matches!(read_puzzle(r#"{"size": 2, "cages": [{"cells": [[0, 2]],
+                                        "op": "const", "goal": 1}]}"#),
+                         Err(Error::CellOutside(_))));
+        assert!(schema_message(r#"{"size": 2, "cages": [{"cells": [], "op": "add",
+                                   "goal": 3}]}"#).starts_with("cages[0].cells:"));
+        assert!(schema_message(r#"{"size": 2, "cages": [], "regions": [[]]}"#)
+                .starts_with("regions[0]:"));
+    }
+
+    fn error_json(text: &str) -> Json {
+        error_to_json(&read_puzzle(text).map(|ken| ken.to_string()).unwrap_err())
+    }
+
+    #[test]
+    fn errors() {
+        let json = error_json("{\"size\": 2,\n \"cages\": [}");
+        assert_eq!(json.get("message"), Some(&Json::Str("invalid JSON: expected value".into())));
+        assert_eq!((json.get("line"), json.get("column")), (Some(&num(2)), Some(&num(12))));
+        assert_eq!(json.get("cell"), None);
+        let json = error_json(r#"{"size": 2, "cages": [{"cells": [[1, 0]], "op": "const",
+                                 "goal": 1}, {"cells": [[1, 0]], "op": "const", "goal": 2}]}"#);
+        assert_eq!(json.get("message"), Some(&Json::Str("cell is already in another cage".into())));
+        assert_eq!(json.get("cell"), Json::parse("[1, 0]").ok().as_ref());
+        assert_eq!(json.get("line"), None);
+        let json = error_json(r#"{"size": 3, "cages": [{"cells": [[0, 1], [2, 1]], "op": "add",
+                                 "goal": 4}, {"cells": [[1, 2], [2, 2]], "op": "mul",
+                                 "goal": 10}]}"#);
+        assert_eq!(json.get("message"),
+                   Some(&Json::Str("cage cells are not connected; goal 10× can't be reached \
+                                    with this puzzle size".into())));
+        assert_eq!(json.get("problems"), Json::parse(
+            r#"[{"message": "cage cells are not connected", "cell": [0, 1]},
+                {"message": "goal 10× can't be reached with this puzzle size", "cell": [1, 2]}]"#
+        ).ok().as_ref());
+    }
+}
@@ -0,0 +1,260 @@
+// KenKen puzzle solver, (c) 2016 Georg Brandl.
+
+//! Reading and writing game IDs of the "Keen" game from Simon Tatham's
+//! Portable Puzzle Collection.
+//!
+//! A game ID looks like `4:_a__b_4a_3a_ba,s1s3m36a2a7d2d2a4`: the parameters
+//! (only the size is used), then the block structure and the list of clues.
+//!
+//! The block structure lists the internal edges of the grid, first the
+//! vertical ones in reading order, then the horizontal ones column by column.
+//! It is run-length encoded: each letter gives the number of edges that are
+//! not cage boundaries before the next boundary (`_` is zero, `a` is one, and
+//! so on); `z` stands for 26 non-boundaries without a following boundary.
+//! Any letter can be followed by a count to repeat it.
+//!
+//! The clues are given for each cage in the order of their first cell, as an
+//! operation letter (`a`, `s`, `m` or `d`) followed by the goal.  Single-cell
+//! cages have an `a` clue.
+
+use {KenKen, Cage, Op, Error, Pos};
+
+/// Returns the pairs of cells separated by each internal edge, in the order
+/// used by the block structure.
+fn edges(size: usize) -> Vec<(usize, usize)> {
+    let mut res = Vec::with_capacity(2 * size * (size - 1));
+    for row in 0..size {
+        for col in 0..size - 1 {
+            res.push((row * size + col, row * size + col + 1));
+        }
+    }
+    for col in 0..size {
+        for row in 0..size - 1 {
+            res.push((row * size + col, (row + 1) * size + col));
+        }
+    }
+    res
+}
+
+fn find(roots: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while roots[root] != root {
+        root = roots[root];
+    }
+    roots[i] = root;
+    root
+}
+
+/// Parses the run-length encoded block structure, and returns the cages as
+/// lists of cells, ordered by their first cell.
+fn parse_blocks(size: usize, desc: &str) -> Result<Vec<Vec<(usize, usize)>>, Error> {
+    let edges = edges(size);
+    let mut roots = (0..size * size).collect::<Vec<_>>();
+    let mut pos = 0;
+    let mut chars = desc.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let run = match ch {
+            '_' => 0,
+            'a'..='z' => ch as usize - 'a' as usize + 1,
+            _ => return Err(Error::InvalidGameId("invalid character in block structure")),
+        };
+        let mut count = String::new();
+        while let Some(&digit) = chars.peek() {
+            if !digit.is_ascii_digit() {
+                break;
+            }
+            count.push(digit);
+            chars.next();
+        }
+        let count = if count.is_empty() { 1 } else {
+            count.parse().map_err(|_| Error::InvalidGameId("invalid repeat count"))?
+        };
+        // each repetition uses `run` edges and the boundary after them
+        let step = if run == 26 { run } else { run + 1 };
+        if count > (edges.len() + 1 - pos) / step {
+            return Err(Error::InvalidGameId("too much data in block structure"));
+        }
+        for _ in 0..count {
+            for _ in 0..run {
+                let &(p0, p1) = edges.get(pos).ok_or(
+                    Error::InvalidGameId("too much data in block structure"))?;
+                let (r0, r1) = (find(&mut roots, p0), find(&mut roots, p1));
+                roots[r0.max(r1)] = r0.min(r1);
+                pos += 1;
+            }
+            // 'z' is not followed by an edge
+            if run != 26 {
+                pos += 1;
+            }
+        }
+    }
+    // the last edge is a virtual one after the end of the grid
+    if pos != edges.len() + 1 {
+        return Err(Error::InvalidGameId("wrong amount of data in block structure"));
+    }
+    let mut blocks: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut block_of = vec![!0; size * size];
+    for i in 0..size * size {
+        let root = find(&mut roots, i);
+        if block_of[root] == !0 {
+            block_of[root] = blocks.len();
+            blocks.push(Vec::new());
+        }
+        blocks[block_of[root]].push((i / size, i % size));
+    }
+    Ok(blocks)
+}
+
+/// Parses a Keen game ID (with or without the parameters) into a puzzle.
+/// Without parameters, the size must be given.
+pub fn read_game_id(id: &str, size: Option<usize>) -> Result<KenKen, Error> {
+    let id = id.trim();
+    let (size, desc) = match id.find(':') {
+        Some(i) => {
+            let digits = id[..i].chars().take_while(|ch| ch.is_ascii_digit()).collect::<String>();
+            let size = digits.parse().map_err(|_| Error::InvalidGameId("invalid parameters"))?;
+            (size, &id[i + 1..])
+        }
+        None => (size.ok_or(Error::InvalidGameId("missing puzzle size"))?, id),
+    };
+    let mut ken = KenKen::new(size)?;
+    let comma = desc.find(',').ok_or(Error::InvalidGameId("missing clues"))?;
+    let blocks = parse_blocks(size, &desc[..comma])?;
+    let mut clues = desc[comma + 1..].chars().peekable();
+    for cells in blocks {
+        let op = clues.next().ok_or(Error::InvalidGameId("not enough clues"))?;
+        let mut goal = String::new();
+        while let Some(&digit) = clues.peek() {
+            if !digit.is_ascii_digit() {
+                break;
+            }
+            goal.push(digit);
+            clues.next();
+        }
+        let goal = goal.parse().map_err(|_| Error::InvalidGameId("invalid clue"))?;
+        let op = match op {
+            'a' if cells.len() == 1 => Op::Const(goal),
+            'a' => Op::Add(goal),
+            's' => Op::Sub(goal),
+            'm' => Op::Mul(goal),
+            'd' => Op::Div(goal),
+            _ => return Err(Error::InvalidGameId("invalid clue")),
+        };
+        ken.add_cage(Cage::new(cells, op))?;
+    }
+    if clues.next().is_some() {
+        return Err(Error::InvalidGameId("too many clues"));
+    }
+    let diags = ken.validate();
+    if !diags.is_empty() {
+        return Err(Error::Invalid(diags));
+    }
+    Ok(ken)
+}
+
+/// Formats the puzzle as a Keen game ID (including the size parameter).
+///
+/// Note that Keen itself only accepts sizes from 3 to 9.  Puzzles that don't
+/// use the numbers 1 to size, or that have diagonals or regions, can't be
+/// written.
+pub fn to_game_id(ken: &KenKen) -> Result<String, Error> {
+    let size = ken.size();
+    if ken.variant().digits.is_some() || ken.variant().diagonals {
+        return Err(Error::UnsupportedVariant(ken.variant().to_string()));
+    }
+    if ken.regions().next().is_some() {
+        return Err(Error::UnsupportedVariant("regions".into()));
+    }
+    let mut cage_of = Vec::with_capacity(size * size);
+    for row in 0..size {
+        for col in 0..size {
+            cage_of.push(ken.cage_at(row, col)
+                            .ok_or_else(|| Error::NotCovered(Pos::of_cell(row, col)))?);
+        }
+    }
+    // encode the edges, with a virtual boundary at the end
+    let mut runs = Vec::new();
+    let mut run = 0;
+    for (p0, p1) in edges(size).into_iter().chain(Some((0, !0))) {
+        if p1 != !0 && cage_of[p0] == cage_of[p1] {
+            run += 1;
+            continue;
+        }
+        while run > 25 {
+            runs.push('z');
+            run -= 26;
+        }
+        runs.push(if run == 0 { '_' } else { (b'a' + run as u8 - 1) as char });
+        run = 0;
+    }
+    // compress repeated letters
+    let mut res = format!("{}:", size);
+    let mut i = 0;
+    while i < runs.len() {
+        let n = runs[i..].iter().take_while(|&&ch| ch == runs[i]).count();
+        res.push(runs[i]);
+        if n > 2 {
+            res.push_str(&n.to_string());
+        } else if n == 2 {
+            res.push(runs[i]);
+        }
+        i += n;
+    }
+    res.push(',');
+    // clues in order of the cages' first cells
+    let mut seen = vec![false; ken.cages().len()];
+    for &idx in &cage_of {
+        if seen[idx] {
+            continue;
+        }
+        seen[idx] = true;
+        let cage = &ken.cages()[idx];
+        let (op, goal) = match cage.operation() {
+            Op::Const(c) | Op::Add(c) => ('a', i64::from(c)),
+            Op::Sub(g) if cage.cells().len() == 2 => ('s', i64::from(g).abs()),
+            Op::Mul(g) => ('m', i64::from(g)),
+            Op::Div(g) if cage.cells().len() == 2 => ('d', i64::from(g)),
+            op => {
+                let (row, col) = cage.cells()[0];
+                return Err(Error::UnsupportedOp(Pos::of_cell(row, col), op));
+            }
+        };
+        res.push_str(&format!("{}{}", op, goal));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readme_example() {
+        let id = "4:_a__b_4a_3a_ba,s1s3m36a2a7d2d2a4";
+        let ken = read_game_id(id, None).unwrap();
+        let expected = include_str!("../examples/test4.ken").parse::<KenKen>().unwrap();
+        assert!(ken == expected);
+        assert!(read_game_id(&id[2..], Some(4)).unwrap() == expected);
+        assert_eq!(to_game_id(&ken).unwrap(), id);
+    }
+
+    #[test]
+    fn export_round_trip() {
+        for text in &[include_str!("../examples/test6-1.ken"),
+                      include_str!("../examples/test9-1.ken"),
+                      include_str!("../examples/test9-2.ken")] {
+            let ken = text.parse::<KenKen>().unwrap();
+            let id = to_game_id(&ken).unwrap();
+            assert!(read_game_id(&id, None).unwrap() == ken, "{}", id);
+        }
+    }
+
+    #[test]
+    fn invalid_block_structure() {
+        for id in &["4:_a__b_4a_3a_ba_,s1s3m36a2a7d2d2a4", "4:_a__b_4a_3a_b,s1s3m36a2a7d2d2a4",
+                    "4:_a__b_4a_3a_ba3,s1s3m36a2a7d2d2a4", "4:_99999999999999,a1",
+                    "4:_18446744073709551615,a1", "4:z2,a1", "4:_A,a1"] {
+            assert!(matches!(read_game_id(id, None), Err(Error::InvalidGameId(_))), "{}", id);
+        }
+    }
+}
@@ -0,0 +1,1071 @@
+// KenKen puzzle solver, (c) 2016 Georg Brandl.
+
+//! A solver for KenKen puzzles.
+//!
+//! Puzzles can be loaded from `.ken` files with `KenKen::load`, parsed from
+//! strings or readers with `str::parse` and `KenKen::from_reader`, or built up
+//! programmatically with `KenKen::new` and `KenKen::add_cage`.  `KenKen::solve`
+//! then finds the unique solution, and `Constraints` can be used to inspect
+//! the candidates that remain for each cell and cage after propagation.
+
+pub mod helpers;
+pub mod error;
+pub mod constraints;
+pub mod generator;
+pub mod json;
+pub mod keen;
+pub mod rating;
+pub mod search;
+pub mod techniques;
+
+use std::cmp::{min, max};
+use std::fmt;
+use std::str::FromStr;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::fs::File;
+
+pub use helpers::{Tbl, BitSet, CandVec, SmallVec, WideVec};
+use helpers::components;
+pub use constraints::Constraints;
+pub use techniques::{Technique, Techniques};
+pub use error::{Error, Diagnostic, Pos};
+pub use search::{Solutions, Strategy};
+
+/// Maximum supported size of a puzzle.
+pub const MAX_SIZE: usize = WideVec::MAX_VAL as usize;
+
+/// Maximum supported number of cells in a cage.
+pub const MAX_CAGE_LEN: usize = WideVec::MAX_LEN;
+
+/// Maximum supported number of cells in a `min`, `max`, `gcd` or `lcm`
+/// cage, and in a multiplication (or long division) cage with a goal of
+/// zero.  Their candidates are found by trying combinations of numbers, so
+/// for larger sizes the limit is lower: the number of combinations (size to
+/// the power of the cells) must not exceed `MAX_COMBINATIONS`.
+pub const MAX_FILTER_CAGE_LEN: usize = 6;
+
+/// Maximum number of combinations of numbers tried for a cage (see
+/// `MAX_FILTER_CAGE_LEN`).
+pub const MAX_COMBINATIONS: usize = 1 << 20;
+
+/// Characters used as cage keys when writing puzzles.  If they run out,
+/// other (non-ASCII) letters are used.
+const KEYS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ@#$%&=<>^~";
+
+/// Returns an iterator over all characters usable as cage keys.
+fn keys() -> Box<dyn Iterator<Item=char>> {
+    Box::new(KEYS.chars().chain((0x100..).filter_map(::std::char::from_u32)
+                                         .filter(|ch| ch.is_alphabetic())))
+}
+
+/// Represents the arithmetic operation in a cage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// A single cell with a given number.
+    Const(i32),
+    /// The cells add up to the goal.
+    Add(i32),
+    /// The difference of the two cells is the goal.  It can be taken in
+    /// either order, so a negative goal means the same as a positive one.
+    Sub(i32),
+    /// The cells multiply to the goal.
+    Mul(i32),
+    /// The quotient of the two cells is the goal.
+    Div(i32),
+    /// The operation is hidden: any of addition, subtraction, multiplication
+    /// and division that reaches the goal is possible.
+    Unknown(i32),
+    /// The larger of the two cells modulo the smaller is the goal.
+    Mod(i32),
+    /// One of the two cells raised to the power of the other is the goal.
+    Pow(i32),
+    /// The smallest number in the cells is the goal.
+    Min(i32),
+    /// The largest number in the cells is the goal.
+    Max(i32),
+    /// The greatest common divisor of the cells is the goal.
+    Gcd(i32),
+    /// The least common multiple of the cells is the goal.
+    Lcm(i32),
+    /// The decimal digits of the cells, concatenated in reading order, give
+    /// the goal.
+    Concat(i32),
+}
+
+impl Op {
+    /// Returns the goal of the operation (or the number of a constant).
+    pub fn goal(self) -> i32 {
+        match self {
+            Op::Const(g) | Op::Add(g) | Op::Sub(g) | Op::Mul(g) | Op::Div(g) |
+            Op::Unknown(g) | Op::Mod(g) | Op::Pow(g) | Op::Min(g) | Op::Max(g) |
+            Op::Gcd(g) | Op::Lcm(g) | Op::Concat(g) => g,
+        }
+    }
+
+    /// Returns the operator suffix used in the `.ken` format.
+    fn suffix(self) -> &'static str {
+        match self {
+            Op::Const(_) => "",
+            Op::Add(_) => "+",
+            Op::Sub(_) => "-",
+            Op::Mul(_) => "*",
+            Op::Div(_) => "/",
+            Op::Unknown(_) => "?",
+            Op::Mod(_) => "%",
+            Op::Pow(_) => "^",
+            Op::Min(_) => "min",
+            Op::Max(_) => "max",
+            Op::Gcd(_) => "gcd",
+            Op::Lcm(_) => "lcm",
+            Op::Concat(_) => "||",
+        }
+    }
+
+    /// Creates the operation given by a suffix in the `.ken` format.
+    fn from_suffix(suffix: &str, goal: i32) -> Option<Op> {
+        Some(match suffix {
+            "" => Op::Const(goal),
+            "+" => Op::Add(goal),
+            "-" => Op::Sub(goal),
+            "*" => Op::Mul(goal),
+            "/" => Op::Div(goal),
+            "?" => Op::Unknown(goal),
+            "%" => Op::Mod(goal),
+            "^" => Op::Pow(goal),
+            "min" => Op::Min(goal),
+            "max" => Op::Max(goal),
+            "gcd" => Op::Gcd(goal),
+            "lcm" => Op::Lcm(goal),
+            "||" => Op::Concat(goal),
+            _ => return None,
+        })
+    }
+}
+
+
+/// Displays the goal and operation as it is shown in the puzzle.
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Op::Add(goal) => write!(f, "{}+", goal),
+            Op::Sub(goal) => write!(f, "{}-", goal),
+            Op::Mul(goal) => write!(f, "{}×", goal),
+            Op::Div(goal) => write!(f, "{}÷", goal),
+            Op::Const(c)  => write!(f, "{}", c),
+            op => write!(f, "{}{}", op.goal(), op.suffix()),
+        }
+    }
+}
+
+/// Rule variants that a puzzle can use.
+///
+/// In the `.ken` format, they are given in header lines starting with `!`
+/// before the cage map, as options separated by spaces.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Variant {
+    /// Subtraction and division cages may have more than two cells: the
+    /// largest number minus (or divided by) all others gives the goal
+    /// (option `long-sub-div`).
+    pub long_sub_div: bool,
+    /// Both main diagonals must contain each number once, like rows and
+    /// columns (option `diagonals`).
+    pub diagonals: bool,
+    /// The numbers used in the puzzle, in ascending order, if they are not
+    /// 1 to size (option `digits=`, e.g. `digits=0-5` or `digits=1,2,4,8`).
+    /// They may be negative, but must not span more than `MAX_SIZE`
+    /// including zero (e.g. `digits=-3-3` for -3 to 3).
+    pub digits: Option<Vec<i32>>,
+}
+
+impl Variant {
+    /// Returns true if no variant rules are used.
+    pub fn is_standard(&self) -> bool {
+        *self == Variant::default()
+    }
+
+    /// Sets the header option given by `word`.  Returns false if the option
+    /// is unknown.
+    pub fn set_option(&mut self, word: &str) -> bool {
+        match word {
+            "long-sub-div" => self.long_sub_div = true,
+            "diagonals" => self.diagonals = true,
+            _ if word.starts_with("digits=") => match parse_digits(&word[7..]) {
+                Some(digits) => self.digits = Some(digits),
+                None => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Parses a comma-separated list of numbers and ranges like `0-5` or `-3-3`,
+/// giving the numbers in ascending order.
+fn parse_digits(list: &str) -> Option<Vec<i32>> {
+    let mut digits = Vec::new();
+    for item in list.split(',') {
+        // a minus sign at the start belongs to the first number
+        let (from, to): (i32, i32) = match item.get(1..).and_then(|rest| rest.find('-')) {
+            Some(i) => (item[..i + 1].parse().ok()?, item[i + 2..].parse().ok()?),
+            None => { let n = item.parse().ok()?; (n, n) }
+        };
+        if from > to || from < -(MAX_SIZE as i32) || to > MAX_SIZE as i32 {
+            return None;
+        }
+        digits.extend(from..=to);
+    }
+    digits.sort();
+    digits.dedup();
+    // the numbers are stored relative to the smallest one, or zero
+    if digits[digits.len() - 1] - min(digits[0], 0) > MAX_SIZE as i32 {
+        return None;
+    }
+    Some(digits)
+}
+
+/// Formats numbers in ascending order as accepted by `parse_digits`, with
+/// runs of three or more numbers written as ranges.
+fn format_digits(digits: &[i32]) -> String {
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < digits.len() {
+        let n = (i..digits.len()).take_while(|&j| digits[j] == digits[i] + (j - i) as i32).count();
+        if n > 2 {
+            items.push(format!("{}-{}", digits[i], digits[i + n - 1]));
+        } else {
+            items.extend(digits[i..i + n].iter().map(|d| d.to_string()));
+        }
+        i += n;
+    }
+    items.join(",")
+}
+
+/// Displays the header options (without the `!`).
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut opts = Vec::new();
+        if self.long_sub_div {
+            opts.push("long-sub-div".into());
+        }
+        if self.diagonals {
+            opts.push("diagonals".into());
+        }
+        if let Some(ref digits) = self.digits {
+            opts.push(format!("digits={}", format_digits(digits)));
+        }
+        write!(f, "{}", opts.join(" "))
+    }
+}
+
+/// Represents a single cage in a puzzle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cage {
+    /// List of cell coordinates that belong to the cage.
+    cells: Vec<(usize, usize)>,
+    /// Operation and goal value of the cage.
+    operation: Op,
+}
+
+impl Cage {
+    /// Creates a new cage from a list of (row, col) cells and an operation.
+    pub fn new(cells: Vec<(usize, usize)>, operation: Op) -> Cage {
+        Cage { cells, operation }
+    }
+
+    /// Returns the cells of the cage, as (row, col) coordinates.
+    pub fn cells(&self) -> &[(usize, usize)] {
+        &self.cells
+    }
+
+    /// Returns the operation and goal of the cage.
+    pub fn operation(&self) -> Op {
+        self.operation
+    }
+}
+
+/// The kind of a house, i.e. a group of cells that must contain each number
+/// once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HouseKind {
+    Row,
+    Column,
+    /// The main diagonal, from top left to bottom right (only with the
+    /// diagonals variant).
+    Diagonal,
+    /// The diagonal from top right to bottom left.
+    AntiDiagonal,
+    /// A region declared by the puzzle (see `KenKen::add_region`).
+    Region,
+}
+
+impl fmt::Display for HouseKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            HouseKind::Row => "row",
+            HouseKind::Column => "column",
+            HouseKind::Diagonal => "diagonal",
+            HouseKind::AntiDiagonal => "anti-diagonal",
+            HouseKind::Region => "region",
+        })
+    }
+}
+
+/// A group of cells that must contain each number once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct House {
+    pub kind: HouseKind,
+    /// The (row, col) cells of the house; there are always `size` of them.
+    pub cells: Vec<(usize, usize)>,
+}
+
+/// Represents a complete puzzle.
+#[derive(Clone)]
+pub struct KenKen {
+    /// Size of the puzzle (number of cells is size*size).
+    size: usize,
+    /// All cages.
+    cages: Vec<Cage>,
+    /// Mapping of cell (row, col) to (cage index, index within cage's cells).
+    cell2cage: Tbl<(usize, usize)>,
+    /// Rule variants used by the puzzle.
+    variant: Variant,
+    /// All houses: rows, columns, diagonals and regions.
+    houses: Vec<House>,
+    /// Number of header lines before the cage map, if loaded from a file.
+    header_lines: usize,
+}
+
+impl KenKen {
+    /// Creates an empty puzzle of the given size, without any cages.
+    pub fn new(size: usize) -> Result<KenKen, Error> {
+        KenKen::with_variant(size, Variant::default())
+    }
+
+    /// Creates an empty puzzle of the given size that uses variant rules.
+    pub fn with_variant(size: usize, variant: Variant) -> Result<KenKen, Error> {
+        if !(2..=MAX_SIZE).contains(&size) {
+            return Err(Error::InvalidSize(None, size));
+        }
+        if let Some(ref digits) = variant.digits {
+            if digits.len() != size {
+                return Err(Error::DigitCount { pos: None, size, found: digits.len() });
+            }
+        }
+        let mut houses = Vec::new();
+        let mut add = |kind, cells| houses.push(House { kind, cells });
+        for i in 0..size {
+            add(HouseKind::Row, (0..size).map(|j| (i, j)).collect());
+        }
+        for i in 0..size {
+            add(HouseKind::Column, (0..size).map(|j| (j, i)).collect());
+        }
+        if variant.diagonals {
+            add(HouseKind::Diagonal, (0..size).map(|i| (i, i)).collect());
+            add(HouseKind::AntiDiagonal, (0..size).map(|i| (i, size - 1 - i)).collect());
+        }
+        Ok(KenKen { size, cages: Vec::new(), cell2cage: Tbl::square(size, (!0, 0)),
+                    variant, houses, header_lines: 0 })
+    }
+
+    /// Returns the rule variants used by the puzzle.
+    pub fn variant(&self) -> &Variant {
+        &self.variant
+    }
+
+    /// Returns the numbers used in the puzzle, in ascending order.  Unless
+    /// the variant declares other numbers, these are 1 to size.
+    pub fn digits(&self) -> Vec<i32> {
+        match self.variant.digits {
+            Some(ref digits) => digits.clone(),
+            None => (1..self.size as i32 + 1).collect(),
+        }
+    }
+
+    /// Returns the offset of numbers in `BitSet`s and candidate vectors,
+    /// which can only hold small non-negative numbers: the number n is
+    /// stored as n - offset.  This is only nonzero if the puzzle uses
+    /// negative numbers.
+    pub fn offset(&self) -> i32 {
+        min(self.digits()[0], 0)
+    }
+
+    /// Returns the numbers used in the puzzle as a set (stored with the
+    /// offset).
+    pub fn digit_set(&self) -> BitSet {
+        match self.variant.digits {
+            Some(ref digits) => {
+                let mut set = BitSet::new_empty();
+                for &d in digits {
+                    set.set((d - self.offset()) as u32);
+                }
+                set
+            }
+            None => BitSet::new_full(self.size),
+        }
+    }
+
+    /// Returns the position of the cell in the `.ken` file.
+    fn cell_pos(&self, row: usize, col: usize) -> Pos {
+        Pos::of_cell(row + self.header_lines, col)
+    }
+
+    /// Returns true if the goal of `op` can possibly be reached with
+    /// `ncells` cells.
+    fn reachable(&self, op: Op, ncells: u32) -> bool {
+        let digits = self.digits();
+        let (lo, hi) = (digits[0], digits[digits.len() - 1]);
+        let n = ncells as i32;
+        let zero = digits.contains(&0);
+        // the largest absolute value, and the smallest one except zero
+        let abs_max = max(lo.abs(), hi.abs());
+        let abs_min = digits.iter().filter(|&&d| d != 0).map(|d| d.abs()).min().unwrap_or(1);
+        let long = self.variant.long_sub_div && ncells > 2;
+        match op {
+            Op::Const(c) => digits.contains(&c),
+            Op::Add(goal) => (n * lo..=n * hi).contains(&goal),
+            // the other numbers are between the smallest and the largest
+            Op::Sub(goal) if long => ((2 - n) * hi..=hi - (n - 1) * lo).contains(&goal),
+            Op::Sub(goal) => (1..=(hi - lo) as u32).contains(&goal.unsigned_abs()),
+            // zero divided by another number gives zero; with more cells, zero
+            // must be the largest number
+            Op::Div(0) => zero && (!long || lo < 0),
+            Op::Mul(0) | Op::Pow(0) | Op::Lcm(0) => zero,
+            Op::Mul(goal) | Op::Div(goal) if goal < 0 && lo >= 0 => false,
+            Op::Div(goal) if long => (1..=abs_max as u32).contains(&goal.unsigned_abs()),
+            Op::Div(goal) =>
+                goal != 1 && (1..=(abs_max / abs_min) as u32).contains(&goal.unsigned_abs()),
+            Op::Mul(goal) => (abs_max as u32).checked_pow(ncells)
+                                             .is_none_or(|m| goal.unsigned_abs() <= m),
+            Op::Unknown(goal) => [Op::Add(goal), Op::Mul(goal), Op::Sub(goal), Op::Div(goal)]
+                .iter().any(|&op| self.allows(op, ncells as usize) && self.reachable(op, ncells)),
+            // only numbers from zero on are used; the smaller number is at
+            // most the largest minus 1
+            Op::Mod(goal) => goal >= 0 && goal <= hi - 2,
+            Op::Pow(goal) => goal > 0 && hi > 0 && (hi as u32).checked_pow(hi as u32)
+                                                          .is_none_or(|m| goal as u32 <= m),
+            Op::Min(goal) | Op::Max(goal) => (lo..=hi).contains(&goal),
+            Op::Gcd(goal) => (1..=hi).contains(&goal),
+            Op::Lcm(goal) | Op::Concat(goal) => goal >= 1,
+        }
+    }
+
+    /// Returns true if the operation is allowed for a cage with `ncells`
+    /// cells.
+    fn allows(&self, op: Op, ncells: usize) -> bool {
+        let min = if let Op::Const(_) = op { 1 } else { 2 };
+        (min..=self.max_cells(op)).contains(&ncells)
+    }
+
+    /// Returns the maximum number of cells of a cage with the operation.
+    fn max_cells(&self, op: Op) -> usize {
+        match op {
+            Op::Const(_) => 1,
+            Op::Min(_) | Op::Max(_) | Op::Gcd(_) | Op::Lcm(_) | Op::Mul(0) =>
+                self.max_filter_cage_len(),
+            Op::Div(0) if self.variant.long_sub_div => self.max_filter_cage_len(),
+            Op::Sub(_) | Op::Div(_) if self.variant.long_sub_div => MAX_CAGE_LEN,
+            Op::Sub(_) | Op::Div(_) | Op::Mod(_) | Op::Pow(_) => 2,
+            _ => MAX_CAGE_LEN,
+        }
+    }
+
+    /// Returns the maximum number of cells of the cages limited by
+    /// `MAX_FILTER_CAGE_LEN` for the size of this puzzle.
+    pub fn max_filter_cage_len(&self) -> usize {
+        (2..=MAX_FILTER_CAGE_LEN).take_while(|&n| {
+            self.size.checked_pow(n as u32).is_some_and(|c| c <= MAX_COMBINATIONS)
+        }).last().unwrap_or(2)
+    }
+
+    /// Returns the size of the puzzle (the length of a row or column).
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns all cages of the puzzle.
+    pub fn cages(&self) -> &[Cage] {
+        &self.cages
+    }
+
+    /// Returns all houses of the puzzle: the rows, the columns, the
+    /// diagonals if the variant uses them, and the regions.
+    pub fn houses(&self) -> &[House] {
+        &self.houses
+    }
+
+    /// Returns the regions added with `add_region`.
+    pub fn regions(&self) -> impl Iterator<Item=&House> {
+        self.houses.iter().filter(|house| house.kind == HouseKind::Region)
+    }
+
+    /// Returns true if the two cells are in a common house.
+    fn same_house(&self, (row1, col1): (usize, usize), (row2, col2): (usize, usize)) -> bool {
+        row1 == row2 || col1 == col2 || self.houses[2 * self.size..].iter().any(|house| {
+            house.cells.contains(&(row1, col1)) && house.cells.contains(&(row2, col2))
+        })
+    }
+
+    /// Adds a region of cells that must contain each number once, like a
+    /// row or column.  It must have as many cells as the puzzle size, and
+    /// must not overlap other regions.
+    pub fn add_region(&mut self, cells: Vec<(usize, usize)>) -> Result<(), Error> {
+        let (row, col) = cells.first().cloned().unwrap_or((0, 0));
+        if cells.len() != self.size {
+            return Err(Error::RegionSize { pos: self.cell_pos(row, col), expected: self.size,
+                                           found: cells.len() });
+        }
+        for (i, &(row, col)) in cells.iter().enumerate() {
+            if row >= self.size || col >= self.size {
+                return Err(Error::CellOutside(self.cell_pos(row, col)));
+            }
+            if cells[..i].contains(&(row, col)) ||
+                self.regions().any(|region| region.cells.contains(&(row, col)))
+            {
+                return Err(Error::RegionOverlap(self.cell_pos(row, col)));
+            }
+        }
+        self.houses.push(House { kind: HouseKind::Region, cells });
+        Ok(())
+    }
+
+    /// Returns the index of the cage that the cell at (row, col) belongs to,
+    /// or None if the cell is not yet covered by a cage.
+    pub fn cage_at(&self, row: usize, col: usize) -> Option<usize> {
+        match *self.cell2cage.get(row, col) {
+            (idx, _) if idx == !0 => None,
+            (idx, _) => Some(idx),
+        }
+    }
+
+    /// Returns true if the puzzle's numbers or cages are too large for
+    /// `SmallVec`, so that `WideVec` must be used to represent candidates.
+    pub fn needs_wide(&self) -> bool {
+        self.digits().last().is_some_and(|&d| (d - self.offset()) as u32 > SmallVec::MAX_VAL) ||
+            self.cages.iter().any(|cage| cage.cells.len() > SmallVec::MAX_LEN)
+    }
+
+    /// Adds a new cage to the puzzle, checking that its cells are free and
+    /// that the operation fits the number of cells.
+    pub fn add_cage(&mut self, cage: Cage) -> Result<(), Error> {
+        let (row, col) = cage.cells.first().cloned().unwrap_or((0, 0));
+        let pos = self.cell_pos(row, col);
+        let ncells = cage.cells.len();
+        if !self.allows(cage.operation, ncells) {
+            return Err(Error::CageSize { pos, op: cage.operation, cells: ncells,
+                                         max: self.max_cells(cage.operation) });
+        }
+        if cage.operation == Op::Const(0) && !self.digits().contains(&0) {
+            return Err(Error::InvalidGoal(pos, cage.operation));
+        }
+        for &(row, col) in &cage.cells {
+            if row >= self.size || col >= self.size {
+                return Err(Error::CellOutside(self.cell_pos(row, col)));
+            }
+            if self.cage_at(row, col).is_some() {
+                return Err(Error::CellTaken(self.cell_pos(row, col)));
+            }
+        }
+        for (i, &(row, col)) in cage.cells.iter().enumerate() {
+            self.cell2cage.put(row, col, (self.cages.len(), i));
+        }
+        self.cages.push(cage);
+        Ok(())
+    }
+
+    /// Load a puzzle from a file.
+    pub fn load(filename: &str) -> Result<KenKen, Error> {
+        KenKen::from_reader(BufReader::new(File::open(filename)?))
+    }
+
+    /// Save the puzzle to a file, in the format read by `load`.
+    pub fn save(&self, filename: &str) -> Result<(), Error> {
+        let mut file = File::create(filename)?;
+        write!(file, "{}", self)?;
+        Ok(())
+    }
+
+    /// Read a puzzle in `.ken` format from a reader.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<KenKen, Error> {
+        let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
+        let mut it = lines.iter().enumerate().peekable();
+        // Read the header lines with variant options.
+        let mut variant = Variant::default();
+        let mut header_lines = 0;
+        let mut digits_pos = None;
+        while let Some(&(lineno, line)) = it.peek() {
+            if !line.starts_with('!') {
+                break;
+            }
+            let mut col = 2;
+            for word in line[1..].split(' ') {
+                if !word.is_empty() && !variant.set_option(word) {
+                    return Err(Error::InvalidOption(Pos { line: lineno + 1, col }, word.into()));
+                }
+                if word.starts_with("digits=") {
+                    digits_pos = Some(Pos { line: lineno + 1, col });
+                }
+                col += word.chars().count() + 1;
+            }
+            header_lines += 1;
+            it.next();
+        }
+        // cells and operation for each cage key
+        let mut cages = BTreeMap::new();
+        let size = it.peek().map_or(0, |l| l.1.chars().count());
+        let mut ken = KenKen::with_variant(size, variant).map_err(|e| match e {
+            Error::InvalidSize(_, size) =>
+                Error::InvalidSize(Some(Pos { line: header_lines + 1, col: 1 }), size),
+            Error::DigitCount { size, found, .. } =>
+                Error::DigitCount { pos: digits_pos, size, found },
+            e => e,
+        })?;
+        ken.header_lines = header_lines;
+        let mut diags = Vec::new();
+        // Read the puzzle cage definition (first part).
+        for (row, line) in it.by_ref().map(|(lineno, line)| (lineno - header_lines, line)) {
+            if line.is_empty() {
+                break;
+            }
+            let len = line.chars().count();
+            if len != size {
+                return Err(Error::UnequalLineLength { pos: ken.cell_pos(row, min(len, size)),
+                                                      expected: size, found: len });
+            }
+            for (col, ch) in line.chars().enumerate() {
+                if let Some(val) = ch.to_digit(10) {
+                    ken.add_cage(Cage::new(vec![(row, col)], Op::Const(val as i32)))?;
+                } else {
+                    cages.entry(ch).or_insert_with(|| (Vec::with_capacity(6), None))
+                                   .0.push((row, col));
+                }
+            }
+        }
+        // Read the cage's operation definitions, one per line.
+        for (lineno, line) in it.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+            let parts = line.split(": ").collect::<Vec<_>>();
+            if parts.len() != 2 || parts[0].chars().count() != 1 || parts[1].is_empty() {
+                return Err(Error::InvalidRule(Pos { line: lineno + 1, col: 1 }));
+            }
+            let key = parts[0].chars().next().unwrap_or(' ');
+            let rule_pos = Pos { line: lineno + 1, col: 1 };
+            let cage = match cages.get_mut(&key) {
+                Some(cage) => cage,
+                None => { diags.push(Diagnostic::UnusedRule(rule_pos, key)); continue; }
+            };
+            if cage.1.is_some() {
+                diags.push(Diagnostic::DuplicateRule(rule_pos, key));
+            }
+            let goal_pos = Pos { line: lineno + 1, col: 4 };
+            // a goal without operator is a constant, which allows constants
+            // with more than one digit; a minus sign at the start makes the
+            // goal negative
+            let sign = if parts[1].starts_with('-') { 1 } else { 0 };
+            let split = parts[1][sign..].find(|ch: char| !ch.is_ascii_digit())
+                                        .map_or(parts[1].len(), |i| i + sign);
+            let (goal_str, suffix) = parts[1].split_at(split);
+            let goal = goal_str.parse().map_err(|_| {
+                let num = if goal_str.is_empty() { parts[1] } else { goal_str };
+                Error::InvalidNumber(goal_pos, num.into())
+            })?;
+            let op_pos = Pos { line: lineno + 1, col: 4 + goal_str.len() };
+            cage.1 = Some(Op::from_suffix(suffix, goal)
+                          .ok_or_else(|| Error::InvalidOperator(op_pos, suffix.into()))?);
+        }
+        // Read the optional region map, which looks like the cage map; cells
+        // marked with '.' are not in a region.
+        let mut regions = BTreeMap::new();
+        let mut rest = it.skip_while(|&(_, line)| line.is_empty()).peekable();
+        if let Some(&(first, _)) = rest.peek() {
+            for row in 0..size {
+                let (lineno, line) = match rest.next() {
+                    Some((lineno, line)) if !line.is_empty() => (lineno, line),
+                    _ => return Err(Error::MissingRegionLines(Pos { line: first + row + 1,
+                                                                    col: 1 })),
+                };
+                let len = line.chars().count();
+                if len != size {
+                    return Err(Error::UnequalLineLength {
+                        pos: Pos { line: lineno + 1, col: min(len, size) + 1 },
+                        expected: size, found: len });
+                }
+                for (col, ch) in line.chars().enumerate().filter(|&(_, ch)| ch != '.') {
+                    regions.entry(ch).or_insert_with(Vec::new).push((row, col));
+                }
+            }
+        }
+        // Check the cage definitions and add the cages to the puzzle.
+        for (key, (cells, op)) in cages {
+            let groups = components(&cells);
+            if groups.len() > 1 {
+                let (row, col) = groups[1][0];
+                diags.push(Diagnostic::SharedKey(ken.cell_pos(row, col), key));
+                continue;
+            }
+            match op {
+                None => {
+                    let (row, col) = cells[0];
+                    return Err(Error::MissingGoal(ken.cell_pos(row, col), key));
+                }
+                Some(op) => ken.add_cage(Cage::new(cells, op))?,
+            }
+        }
+        for cells in regions.into_values() {
+            ken.add_region(cells)?;
+        }
+        ken.check_goals(&mut diags);
+        if !diags.is_empty() {
+            diags.sort_by_key(Diagnostic::pos);
+            return Err(Error::Invalid(diags));
+        }
+        Ok(ken)
+    }
+
+    /// Checks the puzzle for problems that would prevent solving it, such as
+    /// cages that are not connected or goals that can't be reached.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diags = Vec::new();
+        for cage in &self.cages {
+            if components(&cage.cells).len() > 1 {
+                let (row, col) = cage.cells[0];
+                diags.push(Diagnostic::Disconnected(self.cell_pos(row, col)));
+            }
+        }
+        self.check_goals(&mut diags);
+        diags
+    }
+
+    /// Adds a diagnostic for every cage whose goal is out of range for the
+    /// number of cells and the puzzle size.
+    fn check_goals(&self, diags: &mut Vec<Diagnostic>) {
+        for cage in &self.cages {
+            if !self.reachable(cage.operation, cage.cells.len() as u32) {
+                let (row, col) = cage.cells[0];
+                diags.push(Diagnostic::Unreachable(self.cell_pos(row, col), cage.operation));
+            }
+        }
+    }
+
+    /// Checks that every cell belongs to a cage, which is needed before
+    /// building `Constraints` for the puzzle.  Returns `NotCovered` for the
+    /// first cell that doesn't.
+    pub fn check_covered(&self) -> Result<(), Error> {
+        match self.cell2cage.as_vec().iter().position(|&(idx, _)| idx == !0) {
+            Some(i) => Err(Error::NotCovered(self.cell_pos(i / self.size, i % self.size))),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the cages with sorted cells, ordered by their first cell.
+    ///
+    /// This is independent of the order in which the cages were added.
+    fn sorted_cages(&self) -> Vec<Cage> {
+        let mut cages = self.cages.iter().map(|cage| {
+            let mut cells = cage.cells.clone();
+            cells.sort();
+            Cage::new(cells, cage.operation)
+        }).collect::<Vec<_>>();
+        cages.sort_by_key(|cage| cage.cells.first().cloned());
+        cages
+    }
+
+    /// Returns the regions with sorted cells, ordered by their first cell.
+    fn sorted_regions(&self) -> Vec<Vec<(usize, usize)>> {
+        let mut regions = self.regions().map(|region| {
+            let mut cells = region.cells.clone();
+            cells.sort();
+            cells
+        }).collect::<Vec<_>>();
+        regions.sort();
+        regions
+    }
+
+    /// Return a vector of "descriptions" for each cell.  For each cage, one cell
+    /// will have the operation, and the other cells will be empty.
+    pub fn get_descs(&self) -> (usize, Vec<String>) {
+        let mut res = vec![String::new(); self.size * self.size];
+        let mut maxlen = 3;  // minimum width (gives a square puzzle)
+        for cage in &self.cages {
+            let &(row, col) = &cage.cells[0];
+            let fmt_op = cage.operation.to_string();
+            maxlen = max(maxlen, fmt_op.chars().count());
+            res[row*self.size + col] = fmt_op;
+        }
+        (maxlen, res)
+    }
+
+    /// Searches for solutions, visiting the cages as given by the strategy,
+    /// and stopping after `limit` solutions have been found.  If `keep` is
+    /// false, the solutions are only counted.
+    pub fn search(&self, limit: Option<usize>, keep: bool,
+                  strategy: Strategy) -> Result<Solutions, Error> {
+        self.check_covered()?;
+        let diags = self.validate();
+        if !diags.is_empty() {
+            return Err(Error::Invalid(diags));
+        }
+        Ok(if self.needs_wide() {
+            search::search::<WideVec>(self, limit, keep, strategy)
+        } else {
+            search::search::<SmallVec>(self, limit, keep, strategy)
+        })
+    }
+
+    /// Solve the puzzle (or return an error).
+    ///
+    /// On success, returns the number of backtracking steps needed and the
+    /// solution grid.  It is an error if the puzzle has more than one solution.
+    pub fn solve(&self) -> Result<(u32, Tbl<i32>), Error> {
+        let mut res = self.search(Some(2), true, Strategy::default())?;
+        if res.count > 1 {
+            Err(Error::MultipleSolutions)
+        } else {
+            res.solutions.pop().ok_or(Error::NoSolution).map(|sol| (res.steps, sol))
+        }
+    }
+
+    /// Returns the first solution found, even if there are more.
+    pub fn solve_first(&self) -> Result<(u32, Tbl<i32>), Error> {
+        let mut res = self.search(Some(1), true, Strategy::default())?;
+        res.solutions.pop().ok_or(Error::NoSolution).map(|sol| (res.steps, sol))
+    }
+
+    /// Returns all solutions, or only the first `limit` ones if given.
+    pub fn solve_all(&self, limit: Option<usize>) -> Result<Solutions, Error> {
+        self.search(limit, true, Strategy::default())
+    }
+
+    /// Counts the solutions without keeping them.  If `limit` is given, the
+    /// search stops after that many solutions.
+    pub fn count_solutions(&self, limit: Option<usize>) -> Result<Solutions, Error> {
+        self.search(limit, false, Strategy::default())
+    }
+}
+
+impl FromStr for KenKen {
+    type Err = Error;
+
+    /// Parse a puzzle in `.ken` format.
+    fn from_str(s: &str) -> Result<KenKen, Error> {
+        KenKen::from_reader(s.as_bytes())
+    }
+}
+
+/// Two puzzles are equal if they have the same cages and regions, regardless
+/// of the order in which they were added.
+impl PartialEq for KenKen {
+    fn eq(&self, other: &KenKen) -> bool {
+        self.size == other.size && self.variant == other.variant &&
+            self.sorted_cages() == other.sorted_cages() &&
+            self.sorted_regions() == other.sorted_regions()
+    }
+}
+
+impl Eq for KenKen {}
+
+/// Formats the puzzle in canonical `.ken` format.
+///
+/// Variant options are written as a header line.  Single-digit constants are
+/// written into the map directly.  All other cages get keys in the order of
+/// their first cell, and their rules are written in the same order.  Regions
+/// are written as a second map after the rules, keyed the same way.  Parsing
+/// the output gives back an equal puzzle, and formatting that puzzle again
+/// gives the same text, as long as all cells are covered by cages.
+impl fmt::Display for KenKen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut map = Tbl::square(self.size, ' ');
+        let mut rules = Vec::new();
+        let mut cage_keys = keys();
+        for cage in self.sorted_cages() {
+            let key = match cage.operation {
+                Op::Const(c) if (0..10).contains(&c) =>
+                    ::std::char::from_digit(c as u32, 10).unwrap_or('?'),
+                op => {
+                    let key = cage_keys.next().unwrap_or('?');
+                    rules.push((key, format!("{}{}", op.goal(), op.suffix())));
+                    key
+                }
+            };
+            for &(row, col) in &cage.cells {
+                map.put(row, col, key);
+            }
+        }
+        if !self.variant.is_standard() {
+            writeln!(f, "!{}", self.variant)?;
+        }
+        for row in map.as_vec().chunks(self.size) {
+            writeln!(f, "{}", row.iter().collect::<String>())?;
+        }
+        writeln!(f)?;
+        for (key, rule) in rules {
+            writeln!(f, "{}: {}", key, rule)?;
+        }
+        if self.regions().next().is_some() {
+            let mut map = Tbl::square(self.size, '.');
+            for (region, key) in self.sorted_regions().iter().zip(keys()) {
+                for &(row, col) in region {
+                    map.put(row, col, key);
+                }
+            }
+            writeln!(f)?;
+            for row in map.as_vec().chunks(self.size) {
+                writeln!(f, "{}", row.iter().collect::<String>())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that `text` is in canonical format: parsing and formatting
+    /// it gives the same text, and parsing that again an equal puzzle.
+    fn round_trip(text: &str) -> KenKen {
+        let ken = text.parse::<KenKen>().unwrap();
+        let formatted = ken.to_string();
+        assert_eq!(formatted, text);
+        assert!(formatted.parse::<KenKen>().unwrap() == ken);
+        ken
+    }
+
+    #[test]
+    fn round_trip_all_ops() {
+        let ken = round_trip("aabcc\nddbef\ngghef\niih4j\nkkllj\n\n\
+                              a: 3+\nb: 2-\nc: 10*\nd: 2/\ne: 8?\nf: 1%\ng: 8^\nh: 2min\n\
+                              i: 5max\nj: 2gcd\nk: 12lcm\nl: 45||\n");
+        let ops = ken.sorted_cages().iter().map(|c| c.operation).collect::<Vec<_>>();
+        assert_eq!(ops, [Op::Add(3), Op::Sub(2), Op::Mul(10), Op::Div(2), Op::Unknown(8),
+                         Op::Mod(1), Op::Pow(8), Op::Min(2), Op::Max(5), Op::Const(4),
+                         Op::Gcd(2), Op::Lcm(12), Op::Concat(45)]);
+    }
+
+    #[test]
+    fn round_trip_variant() {
+        let ken = round_trip("!long-sub-div digits=-12,-1,10,12\nabcd\neeff\nghhh\ngijk\n\n\
+                              a: 10\nb: 12\nc: -1\nd: -12\ne: -13+\nf: 22+\ng: -120*\n\
+                              h: 11-\ni: 10\nj: 12\nk: -1\n");
+        assert!(ken.variant().long_sub_div && !ken.variant().diagonals);
+        assert_eq!(ken.digits(), [-12, -1, 10, 12]);
+        assert_eq!(ken.cage_at(3, 1).map(|i| ken.cages()[i].operation), Some(Op::Const(10)));
+        assert_eq!(ken.cage_at(1, 0).map(|i| ken.cages()[i].operation), Some(Op::Add(-13)));
+    }
+
+    #[test]
+    fn round_trip_regions() {
+        let ken = round_trip("!diagonals\naab1\ncdbe\ncd2e\n3ffe\n\n\
+                              a: 5+\nb: 7+\nc: 4+\nd: 3*\ne: 8*\nf: 3-\n\n\
+                              aabb\naabb\nccdd\nccdd\n");
+        assert!(ken.variant().diagonals);
+        assert_eq!(ken.regions().count(), 4);
+    }
+
+    #[test]
+    fn long_div_zero_dividend() {
+        // zero is the largest number of the cage, divided by -1 and -5
+        let ken = "!long-sub-div digits=-5,-1,0,3,7\nkkk37\na037b\n037cd\n37ef0\n7gh03\n\n\
+                   k: 0/\na: -1\nb: -5\nc: -5\nd: -1\ne: -5\nf: -1\ng: -5\nh: -1\n"
+            .parse::<KenKen>().unwrap();
+        let (_, solution) = ken.solve().unwrap();
+        assert_eq!(&solution.as_vec()[..5], [-5, -1, 0, 3, 7]);
+    }
+
+    #[test]
+    fn filter_cage_len() {
+        let lens = [4, 9, 10, 11, 16, 17, 32, 33, 63].iter()
+            .map(|&size| KenKen::new(size).unwrap().max_filter_cage_len()).collect::<Vec<_>>();
+        assert_eq!(lens, [6, 6, 6, 5, 5, 4, 4, 3, 3]);
+        let mut ken = KenKen::new(63).unwrap();
+        let cells = vec![(0, 0), (0, 1), (0, 2), (0, 3)];
+        assert!(matches!(ken.add_cage(Cage::new(cells.clone(), Op::Min(1))),
+                         Err(Error::CageSize { cells: 4, max: 3, .. })));
+        assert!(ken.add_cage(Cage::new(cells[..3].to_vec(), Op::Min(1))).is_ok());
+        let digits = Variant { digits: Some(vec![0, 1, 2, 3]), ..Variant::default() };
+        let mut ken = KenKen::with_variant(4, digits).unwrap();
+        let cells = (0..4).map(|col| (0, col)).chain((0..3).map(|col| (1, col))).collect();
+        assert!(matches!(ken.add_cage(Cage::new(cells, Op::Mul(0))),
+                         Err(Error::CageSize { cells: 7, max: 6, .. })));
+    }
+
+    #[test]
+    fn invalid_size_position() {
+        for &(text, line) in &[("a\n\na: 1\n", 1),
+                               ("!diagonals\n!long-sub-div\na\n\na: 1\n", 3)] {
+            match text.parse::<KenKen>() {
+                Err(e) => assert_eq!(e.pos(), Some(Pos { line, col: 1 })),
+                Ok(_) => panic!("size 1 accepted"),
+            }
+        }
+        assert!(KenKen::new(64).err().and_then(|e| e.pos()).is_none());
+    }
+
+    #[test]
+    fn digit_count_position() {
+        match "!diagonals digits=0-4\n!long-sub-div\nab\nab\n\na: 1-\nb: 1-\n".parse::<KenKen>() {
+            Err(e @ Error::DigitCount { size: 2, found: 5, .. }) =>
+                assert_eq!(e.pos(), Some(Pos { line: 1, col: 12 })),
+            res => panic!("expected a digit count error, got {:?}", res.map(|k| k.to_string())),
+        }
+        let digits = Variant { digits: Some(vec![0, 1, 2]), ..Variant::default() };
+        assert!(matches!(KenKen::with_variant(2, digits),
+                         Err(Error::DigitCount { pos: None, size: 2, found: 3 })));
+    }
+
+    #[test]
+    fn all_diagnostics() {
+        let text = "!long-sub-div\naabc\ndbbc\ndeea\nfff1\n\na: 3+\nb: 9+\nc: 5+\nc: 6+\nd: 5+\n\
+                    e: 20*\nf: 6+\nx: 3\n";
+        match text.parse::<KenKen>() {
+            Err(Error::Invalid(diags)) =>
+                assert_eq!(diags, [Diagnostic::Unreachable(Pos { line: 4, col: 2 }, Op::Mul(20)),
+                                   Diagnostic::SharedKey(Pos { line: 4, col: 4 }, 'a'),
+                                   Diagnostic::DuplicateRule(Pos { line: 10, col: 1 }, 'c'),
+                                   Diagnostic::UnusedRule(Pos { line: 14, col: 1 }, 'x')]),
+            res => panic!("expected diagnostics, got {:?}", res.map(|ken| ken.to_string())),
+        }
+        // disconnected cages can only be built directly
+        let mut ken = KenKen::new(3).unwrap();
+        ken.add_cage(Cage::new(vec![(0, 1), (1, 0)], Op::Add(3))).unwrap();
+        ken.add_cage(Cage::new(vec![(2, 2), (2, 1)], Op::Mul(10))).unwrap();
+        assert_eq!(ken.validate(), [Diagnostic::Disconnected(Pos { line: 1, col: 2 }),
+                                    Diagnostic::Unreachable(Pos { line: 3, col: 3 }, Op::Mul(10))]);
+    }
+
+    #[test]
+    fn count_solutions() {
+        // the last two rows can be swapped
+        let ken = "123\naaa\nbbb\n\na: 6+\nb: 6+\n".parse::<KenKen>().unwrap();
+        let res = ken.count_solutions(None).unwrap();
+        assert_eq!((res.count, res.limit_reached, res.solutions.len()), (2, false, 0));
+        let res = ken.count_solutions(Some(1)).unwrap();
+        assert_eq!((res.count, res.limit_reached), (1, true));
+        let res = ken.solve_all(None).unwrap();
+        assert_eq!((res.count, res.limit_reached, res.solutions.len()), (2, false, 2));
+        assert_eq!(search::differing_cells(&res.solutions),
+                   [(1, 0), (1, 1), (1, 2), (2, 0), (2, 1), (2, 2)]);
+        assert!(matches!(ken.solve(), Err(Error::MultipleSolutions)));
+        assert_eq!(ken.solve_first().unwrap().1.as_vec()[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn min_goal_product() {
+        // 46^6 is larger than 2^31, so the goal can't be ruled out early
+        for op in &["*", "?"] {
+            let text = format!("!digits=-1,1,2,46\naaab\naaab\ncdef\ncdef\n\n\
+                                a: -2147483648{}\nb: 47+\nc: 1+\nd: 1+\ne: 1+\nf: 1+\n", op);
+            let ken = text.parse::<KenKen>().unwrap();
+            assert!(matches!(ken.solve(), Err(Error::NoSolution)), "{}", op);
+        }
+    }
+
+    #[test]
+    fn canonical_format() {
+        // keys are renamed in order, and constants go into the map
+        let ken = "bba\ncda\ncde\n\na: 3+\nb: 4+\nc: 5+\nd: 6*\ne: 3\n".parse::<KenKen>().unwrap();
+        assert_eq!(ken.to_string(), "aab\ncdb\ncd3\n\na: 4+\nb: 3+\nc: 5+\nd: 6*\n");
+    }
+}
@@ -1,236 +1,354 @@
 // KenKen puzzle solver, (c) 2016 Georg Brandl.
 
-mod helpers;
-mod constraints;
+extern crate kenken;
 
-use std::cmp::max;
-use std::collections::BTreeMap;
-use std::error::Error;
 use std::env::args;
-use std::io::{BufRead, BufReader};
 use std::fs::File;
+use std::io::{stdin, Read};
+use std::process::exit;
 use std::time::Instant;
-use helpers::{Tbl, RowColMask};
-use constraints::Constraints;
-
-/// Represents the arithmetic operation in a cage.
-pub enum Op {
-    Const(u32),
-    Add(u32),
-    Sub(u32),
-    Mul(u32),
-    Div(u32),
+use kenken::{KenKen, Error, Constraints, Techniques, CandVec, SmallVec, WideVec, Tbl, Strategy};
+use kenken::helpers::format_square;
+use kenken::generator::{self, GenOptions, Rng};
+use kenken::json::{self, Json};
+use kenken::keen;
+use kenken::rating;
+use kenken::search::{self, differing_cells};
+
+/// Which solutions to look for.
+#[derive(PartialEq)]
+enum Mode {
+    /// Find the unique solution; it is an error if there are more.
+    Unique,
+    /// Find the first solution.
+    First,
+    /// Find and show all solutions.
+    All,
+    /// Only count the solutions.
+    Count,
 }
 
-/// Represents a single cage in a puzzle.
-pub struct Cage {
-    /// List of cell coordinates that belong to the cage.
-    cells: Vec<(usize, usize)>,
-    /// Operation and goal value of the cage.
-    operation: Op,
+/// Format of puzzles (and results) read and written.
+#[derive(PartialEq)]
+enum Format {
+    /// The `.ken` format, with results as text.
+    Ken,
+    /// JSON, with results as JSON.
+    Json,
+    /// Keen game IDs, with results as text.
+    Keen,
 }
 
-impl Cage {
-    /// Creates a new cage.  The operation is initially Const() because it
-    /// is either Const or read afterwards.
-    fn new(val: u32) -> Cage {
-        Cage { cells: Vec::with_capacity(6), operation: Op::Const(val) }
-    }
+/// Command line options.
+struct Options {
+    /// Size of the puzzle to generate, if any.
+    generate: Option<usize>,
+    /// Random seed for the generator.
+    seed: Option<u64>,
+    /// Cage size weights for the generator.
+    cage_weights: Option<Vec<u32>>,
+    /// Whether to rate the difficulty of the puzzles.
+    rate: bool,
+    /// Whether to explain the deduction steps (1), and also show the
+    /// candidates after each step (2).
+    explain: u32,
+    /// Which solutions to look for.
+    mode: Mode,
+    /// Maximum number of solutions to look for.
+    limit: Option<usize>,
+    /// Format of puzzles and results.
+    format: Format,
+    /// Deduction techniques to use for explaining, and to report statistics
+    /// for, if given.
+    techniques: Option<Vec<String>>,
+    /// Order in which the search visits the cages.
+    strategy: Strategy,
+    /// Puzzle files to solve.
+    files: Vec<String>,
 }
 
-/// Represents a complete puzzle.
-pub struct KenKen {
-    /// Size of the puzzle (number of cells is size*size).
-    size: usize,
-    /// All cages.
-    cages: Vec<Cage>,
-    /// Mapping of cell (row, col) to (cage index, index within cage's cells).
-    cell2cage: Tbl<(usize, usize)>,
+fn usage() -> ! {
+    println!("Usage: kenken [--rate] [--explain | --explain-grid] [--first | --all | --count]");
+    println!("              [--limit N] [--format ken|json|keen] [--techniques T1,T2,...]");
+    println!("              [--strategy fixed|mrv] puzzle.ken [...]  (- is stdin)");
+    println!("       kenken --generate SIZE [--seed N] [--cages W1,W2,...] [--format ken|json|keen]");
+    exit(1);
 }
 
-impl KenKen {
-    /// Load a puzzle from a file.
-    fn load(filename: &str) -> Result<KenKen, Box<Error>> {
-        let file = try!(File::open(filename));
-        let mut it = BufReader::new(file).lines().enumerate().peekable();
-        let mut cells = BTreeMap::new();
-        let size = it.peek().and_then(|r| r.1.as_ref().map(String::len).ok()).unwrap_or(0);
-        if size < 2 || size > 15 {
-            return Err(format!("kenken size must be < 16 (found {})", size).into());
+fn parse_args() -> Options {
+    let mut opts = Options { generate: None, seed: None, cage_weights: None, rate: false,
+                             explain: 0, mode: Mode::Unique, limit: None, format: Format::Ken,
+                             techniques: None, strategy: Strategy::default(),
+                             files: Vec::new() };
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--generate" => opts.generate = Some(args.next().and_then(|v| v.parse().ok())
+                                                 .unwrap_or_else(|| usage())),
+            "--seed" => opts.seed = Some(args.next().and_then(|v| v.parse().ok())
+                                         .unwrap_or_else(|| usage())),
+            "--cages" => opts.cage_weights = Some(args.next().and_then(|v| {
+                v.split(',').map(|w| w.parse().ok()).collect()
+            }).unwrap_or_else(|| usage())),
+            "--rate" => opts.rate = true,
+            "--explain" => opts.explain = 1,
+            "--explain-grid" => opts.explain = 2,
+            "--first" => opts.mode = Mode::First,
+            "--all" => opts.mode = Mode::All,
+            "--count" => opts.mode = Mode::Count,
+            "--limit" => opts.limit = Some(args.next().and_then(|v| v.parse().ok())
+                                           .unwrap_or_else(|| usage())),
+            "--format" => opts.format = match args.next().as_deref() {
+                Some("ken") => Format::Ken,
+                Some("json") => Format::Json,
+                Some("keen") => Format::Keen,
+                _ => usage(),
+            },
+            "--techniques" => opts.techniques = Some(args.next().and_then(|v| {
+                let known = Techniques::<SmallVec>::standard().names();
+                v.split(',').map(|name| Some(name).filter(|name| known.contains(name))
+                                                  .map(String::from))
+                            .collect()
+            }).unwrap_or_else(|| usage())),
+            "--strategy" => opts.strategy = match args.next().as_deref() {
+                Some("fixed") => Strategy::Fixed,
+                Some("mrv") => Strategy::MinRemaining,
+                _ => usage(),
+            },
+            _ if arg.starts_with("--") => usage(),
+            _ => opts.files.push(arg),
         }
-        let cell2cage = Tbl::square(size, (!0, 0));
-        let mut ken = KenKen { size: size, cages: Vec::new(), cell2cage: cell2cage };
-        // Read the puzzle cage definition (first part).
-        for (row, line) in it.by_ref() {
-            let line = try!(line);
-            if line.is_empty() {
-                break;
-            }
-            if line.len() != size {
-                return Err(format!("unequal line lengths (expected {}, found {})",
-                                   size, line.len()).into());
-            }
-            for (col, ch) in line.chars().enumerate() {
-                let cage = if ch.is_numeric() {
-                    let val = format!("{}", ch).parse().unwrap();
-                    ken.cages.push(Cage::new(val));
-                    ken.cell2cage.put(row, col, (ken.cages.len() - 1, 0));
-                    ken.cages.last_mut().unwrap()
-                } else {
-                    cells.entry(ch).or_insert_with(|| Cage::new(0))
-                };
-                cage.cells.push((row, col));
-            }
-        }
-        // Read the cage's operation definitions, one per line.
-        for (_, line) in it {
-            let line = try!(line);
-            if line.is_empty() {
-                break;
-            }
-            let parts = line.split(": ").collect::<Vec<_>>();
-            if parts.len() != 2 || parts[0].len() != 1 {
-                return Err(format!("invalid line with cage: {}", line).into());
-            }
-            let key = try!(parts[0].chars().nth(0).ok_or("missing char before :"));
-            if !cells.contains_key(&key) {
-                continue;
-            }
-            let cage = try!(cells.get_mut(&key).ok_or(format!("reference to undefined cell {}", key)));
-            let i = parts[1].len();
-            let goal = try!(parts[1][..i-1].parse()
-                            .map_err(|_| format!("invalid number: {}", &parts[1][..i-1])));
-            cage.operation = match &parts[1][i-1..i] {
-                "+" => Op::Add(goal),
-                "-" => Op::Sub(goal),
-                "*" => Op::Mul(goal),
-                "/" => Op::Div(goal),
-                other => return Err(format!("invalid operator: {}", other).into()),
-            };
-        }
-        // Check the cage definitions and add the cages to the puzzle.
-        for (key, cage) in cells {
-            match cage.operation {
-                Op::Sub(_) | Op::Div(_) => if cage.cells.len() != 2 {
-                    return Err(format!("sub/div cages must have 2 cells, not {}", cage.cells.len()).into());
-                },
-                Op::Const(goal) => if goal == 0 {
-                    return Err(format!("found cage ({}) without defined goal", key).into());
-                },
-                _ => if cage.cells.len() < 2 || cage.cells.len() > 15 {
-                    return Err(format!("add/mul cages must have less than 16 cells, not {}",
-                                       cage.cells.len()).into());
-                }
-            }
-            for (i, &(row, col)) in cage.cells.iter().enumerate() {
-                ken.cell2cage.put(row, col, (ken.cages.len(), i));
-            }
-            ken.cages.push(cage);
+    }
+    if opts.format == Format::Json && opts.explain > 0 {
+        usage();
+    }
+    opts
+}
+
+fn generate(opts: &Options, size: usize) {
+    let mut gen_opts = GenOptions::new(size);
+    if let Some(ref weights) = opts.cage_weights {
+        gen_opts.cage_weights = weights.clone();
+    }
+    let mut rng = opts.seed.map_or_else(Rng::from_time, Rng::new);
+    match generator::generate(&mut rng, &gen_opts) {
+        Ok(ken) => match opts.format {
+            Format::Ken => print!("{}", ken),
+            Format::Json => println!("{}", json::puzzle_to_json(&ken)),
+            Format::Keen => match keen::to_game_id(&ken) {
+                Ok(id) => println!("{}", id),
+                Err(e) => { println!("*** Error generating puzzle: {}", e); exit(1); }
+            },
+        },
+        Err(e) => { println!("*** Error generating puzzle: {}", e); exit(1); }
+    }
+}
+
+/// Returns the techniques selected on the command line.
+fn select_techniques<V: CandVec>(names: &[String]) -> Techniques<V> {
+    let mut techniques = Techniques::standard();
+    // the names were checked in parse_args
+    let _ = techniques.select(&names.iter().map(|n| &**n).collect::<Vec<_>>());
+    techniques
+}
+
+fn explain<V: CandVec>(puzzle: &KenKen, show_grid: bool, techniques: Option<&[String]>) {
+    let mut cons = Constraints::<V>::empty(puzzle);
+    cons.set_observer(Box::new(move |step, cons| {
+        println!("{}", cons.describe(step));
+        if show_grid {
+            print!("{}", cons);
         }
-        Ok(ken)
+    }));
+    if let Some(names) = techniques {
+        *cons.techniques_mut() = select_techniques(names);
     }
+    cons.determine_initial();
+    while cons.reduce() { }
+    println!("Remaining candidates{}:", if cons.is_solved() { "" } else { " (search needed)" });
+    print!("{}", cons);
+}
 
-    /// Return a vector of "descriptions" for each cell.  For each cage, one cell
-    /// will have the operation, and the other cells will be empty.
-    fn get_descs(&self) -> (usize, Vec<String>) {
-        let mut res = vec![String::new(); self.size * self.size];
-        let mut maxlen = 3;  // minimum width (gives a square puzzle)
-        for cage in &self.cages {
-            let &(row, col) = &cage.cells[0];
-            let fmt_op = match cage.operation {
-                Op::Add(goal) => format!("{}+", goal),
-                Op::Sub(goal) => format!("{}-", goal),
-                Op::Mul(goal) => format!("{}×", goal),
-                Op::Div(goal) => format!("{}÷", goal),
-                Op::Const(c)  => format!("{}", c),
-            };
-            maxlen = max(maxlen, fmt_op.chars().count());
-            res[row*self.size + col] = fmt_op;
+/// Prints how much each selected technique contributed, and the search steps
+/// needed after applying them.
+fn print_technique_stats<V: CandVec>(puzzle: &KenKen, names: &[String], limit: Option<usize>,
+                                     strategy: Strategy) {
+    let cons = Constraints::with_techniques(puzzle, select_techniques::<V>(names));
+    for (name, stats) in cons.techniques().stats() {
+        if cons.techniques().is_enabled(name) {
+            println!("{:-20} {:>14} {}", "", name, stats);
         }
-        (maxlen, res)
     }
+    let res = search::search_with(puzzle, &cons, limit, false, strategy);
+    println!("{:-20} {:8} steps with these techniques", "", res.steps);
+}
 
-    /// Solve the puzzle (or return a failure string).
-    fn solve(&self) -> Result<(u32, Tbl<u32>), &'static str> {
-        fn inner(ken: &KenKen, cons: &Constraints, work: &mut Tbl<u32>, res: &mut Vec<Tbl<u32>>,
-                 mask: &mut RowColMask, steps: &mut u32, cageidx: usize)
-        {
-            *steps += 1;
-
-            // try to place each cage candidate in its cells
-            'outer: for cand in cons.get_cage_candidates(cageidx) {
-                // check if we can do it without duplicating numbers in rows/cols
-                for (cellidx, el) in cand.iter().enumerate() {
-                    let (row, col) = ken.cages[cageidx].cells[cellidx];
-                    if !mask.ok(row, col, el) {
-                        continue 'outer;
+fn load(arg: &str, format: &Format) -> Result<KenKen, Error> {
+    if *format == Format::Ken {
+        return if arg == "-" {
+            KenKen::from_reader(stdin().lock())
+        } else {
+            KenKen::load(arg)
+        };
+    }
+    let mut text = String::new();
+    if arg == "-" {
+        stdin().read_to_string(&mut text)?;
+    } else {
+        File::open(arg)?.read_to_string(&mut text)?;
+    }
+    if *format == Format::Json {
+        json::read_puzzle(&text)
+    } else {
+        keen::read_game_id(&text, None)
+    }
+}
+
+/// Solves a puzzle and prints the result as a single line of JSON.
+fn solve_json(opts: &Options, arg: &str) {
+    let mut out = match load(arg, &opts.format) {
+        Err(e) => json::error_to_json(&e),
+        Ok(puzzle) => {
+            let start = Instant::now();
+            let res = match opts.mode {
+                Mode::Unique => puzzle.search(Some(2), true, opts.strategy),
+                Mode::First => puzzle.search(Some(1), true, opts.strategy),
+                Mode::All => puzzle.search(opts.limit, true, opts.strategy),
+                Mode::Count => puzzle.search(opts.limit, false, opts.strategy),
+            };
+            let took = start.elapsed();
+            let took = took.as_secs() as f64 + 1e-9 * took.subsec_nanos() as f64;
+            match res {
+                Err(e) => json::error_to_json(&e),
+                Ok(res) => {
+                    let mut out = json::solutions_to_json(&res, opts.mode == Mode::Unique,
+                                                          took * 1000.);
+                    if opts.rate && res.count > 0 {
+                        if let Ok(r) = rating::rate(&puzzle) {
+                            out.set("rating", json::rating_to_json(&r));
+                        }
                     }
-                }
-                // if yes, do it
-                for (cellidx, el) in cand.iter().enumerate() {
-                    let (row, col) = ken.cages[cageidx].cells[cellidx];
-                    work.put(row, col, el);
-                    mask.clear(row, col, el);
-                }
-                // and recurse
-                if cageidx < ken.cages.len() - 1 {
-                    inner(ken, cons, work, res, mask, steps, cageidx + 1)
-                } else {
-                    res.push(work.clone());  // solution found!
-                }
-                // reset row/colmasks for our candidate
-                for (cellidx, el) in cand.iter().enumerate() {
-                    let (row, col) = ken.cages[cageidx].cells[cellidx];
-                    mask.set(row, col, el);
+                    out
                 }
             }
-            // reset the cells
-            for &(row, col) in &ken.cages[cageidx].cells {
-                work.put(row, col, 0);
-            }
         }
+    };
+    out.set("file", Json::Str(arg.into()));
+    println!("{}", out);
+}
 
-        let mut cons = Constraints::empty(self);
-        cons.determine_initial();
-        while cons.reduce() { }
-
-        let mut work = Tbl::square(self.size, 0);
-        let mut res = Vec::new();
-        let mut mask = RowColMask::new(self.size);
-        let mut steps = 0;
-        inner(self, &cons, &mut work, &mut res, &mut mask, &mut steps, 0);
-        if res.len() > 1 {
-            Err("found more than 1 solution")
-        } else {
-            res.pop().ok_or("found no solution").map(|res| (steps, res))
-        }
+fn print_solution(puzzle: &KenKen, solution: &Tbl<i32>, title: &str) {
+    let (cellsize, descs) = puzzle.get_descs();
+    let out1 = format_square(puzzle, cellsize, &descs);
+    let out2 = format_square(puzzle, cellsize, solution.as_vec());
+    println!("{0:<1$}    {2}", "Puzzle", puzzle.size() * (cellsize + 1), title);
+    for (l1, l2) in out1.lines().zip(out2.lines()) {
+        println!("{}   {}", l1, l2);
     }
 }
 
+fn print_differences(solutions: &[Tbl<i32>]) {
+    let cells = differing_cells(solutions).iter().map(|&(row, col)| {
+        format!("r{}c{}", row + 1, col + 1)
+    }).collect::<Vec<_>>();
+    println!("Cells that differ between solutions: {}", cells.join(", "));
+}
+
 fn main() {
-    let args: Vec<_> = args().skip(1).collect();
-    let show_solution = args.len() == 1;
-    for arg in args {
-        let puzzle = match KenKen::load(&arg) {
+    let opts = parse_args();
+    if let Some(size) = opts.generate {
+        return generate(&opts, size);
+    }
+    let show_solution = opts.files.len() == 1;
+    for arg in &opts.files {
+        if opts.format == Format::Json {
+            solve_json(&opts, arg);
+            continue;
+        }
+        let puzzle = match load(arg, &opts.format) {
+            Err(Error::Invalid(diags)) => {
+                println!("*** Error loading {}:", arg);
+                for diag in diags {
+                    println!("    {}", diag);
+                }
+                continue;
+            }
             Err(e) => { println!("*** Error loading {}: {}", arg, e); continue; }
             Ok(puzzle) => puzzle
         };
+        if opts.explain > 0 {
+            if let Err(e) = puzzle.check_covered() {
+                println!("*** Error solving {}: {}", arg, e);
+                continue;
+            }
+            let techniques = opts.techniques.as_deref();
+            if puzzle.needs_wide() {
+                explain::<WideVec>(&puzzle, opts.explain > 1, techniques);
+            } else {
+                explain::<SmallVec>(&puzzle, opts.explain > 1, techniques);
+            }
+        }
         let start = Instant::now();
-        let (steps, solution) = match puzzle.solve() {
+        let res = match opts.mode {
+            Mode::Unique => puzzle.search(Some(2), true, opts.strategy),
+            Mode::First => puzzle.search(Some(1), true, opts.strategy),
+            Mode::All => puzzle.search(opts.limit, true, opts.strategy),
+            Mode::Count => puzzle.search(opts.limit, false, opts.strategy),
+        };
+        let res = match res {
             Err(e) => { println!("*** Error solving {}: {}", arg, e); continue; }
             Ok(res) => res
         };
         let took = start.elapsed();
         let took = took.as_secs() as f64 + 1e-9 * took.subsec_nanos() as f64;
-        if show_solution {
-            let (cellsize, descs) = puzzle.get_descs();
-            let out1 = helpers::format_square(&puzzle, cellsize, &descs);
-            let out2 = helpers::format_square(&puzzle, cellsize, solution.as_vec());
-            println!("{0:<1$}    Solution", "Puzzle", puzzle.size * (cellsize + 1));
-            for (l1, l2) in out1.lines().zip(out2.lines()) {
-                println!("{}   {}", l1, l2);
+        if res.count == 0 {
+            println!("*** Error solving {}: {}", arg, Error::NoSolution);
+            continue;
+        }
+        if opts.mode == Mode::Unique && res.count > 1 {
+            println!("*** Error solving {}: {}", arg, Error::MultipleSolutions);
+            print_differences(&res.solutions);
+            continue;
+        }
+        if opts.mode == Mode::All {
+            for (i, solution) in res.solutions.iter().enumerate() {
+                print_solution(&puzzle, solution, &format!("Solution {}", i + 1));
+            }
+        } else if show_solution && opts.mode != Mode::Count {
+            print_solution(&puzzle, &res.solutions[0], "Solution");
+        }
+        if opts.mode == Mode::All || opts.mode == Mode::Count {
+            println!("{:-20} {:8} solutions{}", arg, res.count,
+                     if res.limit_reached { " (limit reached)" } else { "" });
+            if res.solutions.len() > 1 {
+                print_differences(&res.solutions);
+            }
+        }
+        println!("{:-20} {:8} steps {:10.4} ms", arg, res.steps, took * 1000.);
+        if let Some(ref names) = opts.techniques {
+            let limit = match opts.mode {
+                Mode::Unique => Some(2),
+                Mode::First => Some(1),
+                _ => opts.limit,
+            };
+            if puzzle.needs_wide() {
+                print_technique_stats::<WideVec>(&puzzle, names, limit, opts.strategy);
+            } else {
+                print_technique_stats::<SmallVec>(&puzzle, names, limit, opts.strategy);
+            }
+        }
+        if opts.rate {
+            match rating::rate(&puzzle) {
+                Err(e) => println!("*** Error rating {}: {}", arg, e),
+                Ok(r) => {
+                    let mut details = r.rounds.iter().filter(|&&(_, rounds)| rounds > 0)
+                                               .map(|&(name, rounds)| format!("{}: {}", name, rounds))
+                                               .collect::<Vec<_>>();
+                    details.push(if r.solved_by_deduction { "no search" } else { "search needed" }
+                                 .into());
+                    println!("{:-20} {:>8} score {:8} ({})", "", r.grade, r.score, details.join(", "));
+                }
             }
         }
-        println!("{:-20} {:8} steps {:10.4} ms", arg, steps, took * 1000.);
     }
 }
@@ -0,0 +1,149 @@
+// KenKen puzzle solver, (c) 2016 Georg Brandl.
+
+use std::fmt;
+
+use {KenKen, Error, Strategy};
+use constraints::Constraints;
+use helpers::{CandVec, SmallVec, WideVec};
+use search::Solutions;
+
+/// Difficulty grade of a puzzle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Grade {
+    /// Solvable with naked singles only.
+    Easy,
+    /// Needs naked pairs, cage/house interactions or innies and outies.
+    Medium,
+    /// Needs hidden singles or subsets (hidden pairs and triples, naked
+    /// triples and quads), or a little guessing.
+    Hard,
+    /// Needs a lot of guessing.
+    Expert,
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            Grade::Easy => "easy",
+            Grade::Medium => "medium",
+            Grade::Hard => "hard",
+            Grade::Expert => "expert",
+        })
+    }
+}
+
+/// Number of search steps (beyond one per cage) up to which a puzzle is
+/// still rated as hard instead of expert.
+const HARD_SEARCH_LIMIT: u32 = 100;
+
+/// Result of rating a puzzle.
+#[derive(Clone, Debug)]
+pub struct Rating {
+    /// Number of rounds in which each deduction technique was needed, in
+    /// the order of the pipeline.
+    pub rounds: Vec<(&'static str, u32)>,
+    /// Whether the puzzle was solved by deduction alone.
+    pub solved_by_deduction: bool,
+    /// Number of steps the backtracking search needed (visiting the cages in
+    /// `Strategy::MinRemaining` order).
+    pub steps: u32,
+    /// Numeric difficulty score (higher is harder).
+    pub score: u32,
+    /// Difficulty grade.
+    pub grade: Grade,
+}
+
+/// What the deduction techniques did for a rating.
+struct Deduction {
+    rounds: Vec<(&'static str, u32)>,
+    /// Sum of the technique weights for each round.
+    score: u32,
+    /// Highest grade of the techniques that were needed.
+    grade: Grade,
+    solved: bool,
+}
+
+/// Applies the deduction techniques, counting the rounds in which each one
+/// was needed.
+fn deduce<V: CandVec>(ken: &KenKen) -> Deduction {
+    let mut cons = Constraints::<V>::empty(ken);
+    cons.determine_initial();
+    while cons.reduce_step().is_some() { }
+    let mut res = Deduction { rounds: Vec::new(), score: 0, grade: Grade::Easy,
+                              solved: cons.is_solved() };
+    for (technique, stats) in cons.techniques().iter() {
+        res.rounds.push((technique.name(), stats.rounds));
+        res.score += technique.weight() * stats.rounds;
+        if stats.rounds > 0 {
+            res.grade = res.grade.max(technique.grade());
+        }
+    }
+    res
+}
+
+/// Rates the difficulty of a puzzle.
+///
+/// Deduction techniques are applied in the order of the standard pipeline,
+/// which is sorted by grade, and a technique is only used when all earlier
+/// ones make no progress; so a harder grade is only given when the easier
+/// techniques are stuck.  Each technique counts the rounds in which it was
+/// needed, which add to the score by the technique's weight.  If deduction
+/// is not enough to solve the puzzle, the steps of the backtracking search
+/// are taken into account as well.  It is an error if the puzzle doesn't
+/// have exactly one solution.
+pub fn rate(ken: &KenKen) -> Result<Rating, Error> {
+    let steps = match ken.search(Some(2), false, Strategy::MinRemaining)? {
+        Solutions { count: 0, .. } => return Err(Error::NoSolution),
+        Solutions { count: 1, steps, .. } => steps,
+        _ => return Err(Error::MultipleSolutions),
+    };
+    let deduction = if ken.needs_wide() {
+        deduce::<WideVec>(ken)
+    } else {
+        deduce::<SmallVec>(ken)
+    };
+
+    // every cage is visited at least once, even without backtracking
+    let search = if deduction.solved {
+        0
+    } else {
+        steps.saturating_sub(ken.cages.len() as u32)
+    };
+    let score = deduction.score + 20 * search;
+    let grade = if search > HARD_SEARCH_LIMIT {
+        Grade::Expert
+    } else if search > 0 {
+        deduction.grade.max(Grade::Hard)
+    } else {
+        deduction.grade
+    };
+    Ok(Rating { rounds: deduction.rounds, solved_by_deduction: deduction.solved, steps, score,
+                grade })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use techniques::Techniques;
+
+    #[test]
+    fn standard_order() {
+        let techniques = Techniques::<SmallVec>::standard();
+        let grades = techniques.iter().map(|(technique, _)| technique.grade()).collect::<Vec<_>>();
+        assert!(grades.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn rate_examples() {
+        let ken = include_str!("../examples/test9-1.ken").parse::<KenKen>().unwrap();
+        let rating = rate(&ken).unwrap();
+        assert_eq!(rating.grade, Grade::Hard);
+        assert!(rating.solved_by_deduction);
+        assert_eq!(rating.rounds.iter().filter(|r| r.1 > 0).cloned().collect::<Vec<_>>(),
+                   [("naked-singles", 8), ("naked-pairs", 2), ("cage-houses", 1),
+                    ("hidden-singles", 1)]);
+        assert_eq!(rating.score, 8 + 5 * 2 + 5 + 10);
+        let ken = include_str!("../examples/test4.ken").parse::<KenKen>().unwrap();
+        assert_eq!(rate(&ken).unwrap().grade, Grade::Easy);
+    }
+}
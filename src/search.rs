@@ -0,0 +1,240 @@
+// KenKen puzzle solver, (c) 2016 Georg Brandl.
+
+use KenKen;
+use constraints::Constraints;
+use helpers::{Tbl, CandVec, HouseMask};
+
+/// Result of a search for solutions.
+pub struct Solutions {
+    /// Number of backtracking steps needed.
+    pub steps: u32,
+    /// Number of solutions found.
+    pub count: usize,
+    /// The solutions found, if they were kept.
+    pub solutions: Vec<Tbl<i32>>,
+    /// True if the search was stopped because the limit (of solutions, or of
+    /// steps for `search_bounded`) was reached, i.e. there may be more
+    /// solutions.
+    pub limit_reached: bool,
+}
+
+/// The order in which the search visits the cages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// In the order of the puzzle's cages.
+    Fixed,
+    /// Next, the cage with the fewest candidates that still fit the numbers
+    /// placed so far ("minimum remaining values").  Ties are broken by the
+    /// number of already placed cages that share a house with it.
+    MinRemaining,
+}
+
+/// The default is `Fixed`, so that step counts and the order of solutions
+/// stay the same as without a choice of strategy.
+impl Default for Strategy {
+    fn default() -> Strategy {
+        Strategy::Fixed
+    }
+}
+
+/// State of the backtracking search.
+struct Search<'a, 'c, V: CandVec + 'c> where 'a: 'c {
+    ken: &'a KenKen,
+    cons: &'c Constraints<'a, V>,
+    strategy: Strategy,
+    work: Tbl<u32>,
+    mask: HouseMask,
+    /// For each cage, whether it is placed, and the other cages that share
+    /// a house with it (only for `Strategy::MinRemaining`).
+    placed: Vec<bool>,
+    neighbors: Vec<Vec<usize>>,
+    steps: u32,
+    max_steps: u32,
+    count: usize,
+    limit: usize,
+    keep: bool,
+    solutions: Vec<Tbl<i32>>,
+}
+
+impl<'a, 'c, V: CandVec> Search<'a, 'c, V> {
+    /// Returns true if the candidate can be placed in the cage's cells
+    /// without duplicating numbers in houses.
+    fn fits(&self, cageidx: usize, cand: &V) -> bool {
+        let cells = &self.ken.cages[cageidx].cells;
+        cand.iter().enumerate().all(|(cellidx, el)| {
+            let (row, col) = cells[cellidx];
+            self.mask.ok(row, col, el)
+        })
+    }
+
+    /// Selects the cage to place next according to the strategy, when
+    /// `depth` cages are already placed.
+    fn next_cage(&self, depth: usize) -> usize {
+        if self.strategy == Strategy::Fixed {
+            return depth;
+        }
+        // (fitting candidates, placed neighbors, index) of the best cage
+        let mut best = (usize::MAX, 0, 0);
+        for cageidx in (0..self.ken.cages.len()).filter(|&idx| !self.placed[idx]) {
+            let mut fitting = 0;
+            for cand in self.cons.get_cage_candidates(cageidx) {
+                if self.fits(cageidx, cand) {
+                    fitting += 1;
+                    if fitting > best.0 {
+                        break;
+                    }
+                }
+            }
+            if fitting > best.0 {
+                continue;
+            }
+            let connected = self.neighbors[cageidx].iter().filter(|&&idx| self.placed[idx])
+                                                          .count();
+            if fitting < best.0 || connected > best.1 {
+                best = (fitting, connected, cageidx);
+                // a cage with a single fitting candidate is taken right away
+                if fitting <= 1 {
+                    break;
+                }
+            }
+        }
+        best.2
+    }
+
+    /// Places candidates for the next cage and recurses into the following
+    /// cages, when `depth` cages are already placed.  Returns true if the
+    /// search should be stopped.
+    fn inner(&mut self, depth: usize) -> bool {
+        let ken = self.ken;
+        let cons = self.cons;
+        let cageidx = self.next_cage(depth);
+        let cells = &ken.cages[cageidx].cells;
+        if self.steps == self.max_steps {
+            return true;
+        }
+        let mut stop = false;
+        self.steps += 1;
+        self.placed[cageidx] = true;
+
+        // try to place each cage candidate in its cells
+        for cand in cons.get_cage_candidates(cageidx) {
+            // check if we can do it without duplicating numbers in houses
+            if !self.fits(cageidx, cand) {
+                continue;
+            }
+            // if yes, do it
+            for (cellidx, el) in cand.iter().enumerate() {
+                let (row, col) = cells[cellidx];
+                self.work.put(row, col, el);
+                self.mask.clear(row, col, el);
+            }
+            // and recurse
+            if depth < ken.cages.len() - 1 {
+                stop = self.inner(depth + 1);
+            } else {
+                // solution found!
+                self.count += 1;
+                if self.keep {
+                    let offset = ken.offset();
+                    self.solutions.push(self.work.map(|&el| el as i32 + offset));
+                }
+                stop = self.count >= self.limit;
+            }
+            // reset house masks for our candidate
+            for (cellidx, el) in cand.iter().enumerate() {
+                let (row, col) = cells[cellidx];
+                self.mask.set(row, col, el);
+            }
+            if stop {
+                break;
+            }
+        }
+        // reset the cells
+        for &(row, col) in cells {
+            self.work.put(row, col, 0);
+        }
+        self.placed[cageidx] = false;
+        stop
+    }
+}
+
+/// Returns, for each cage, the other cages that share a house with it.
+fn neighbors(ken: &KenKen) -> Vec<Vec<usize>> {
+    let mut neighbors = vec![Vec::new(); ken.cages.len()];
+    for house in ken.houses() {
+        let mut cages = house.cells.iter().map(|&(row, col)| ken.cell2cage.get(row, col).0)
+                                          .collect::<Vec<_>>();
+        cages.sort();
+        cages.dedup();
+        for &idx in &cages {
+            neighbors[idx].extend(cages.iter().filter(|&&other| other != idx));
+        }
+    }
+    for list in &mut neighbors {
+        list.sort();
+        list.dedup();
+    }
+    neighbors
+}
+
+/// Searches for solutions of the puzzle, visiting the cages as given by the
+/// strategy, and stopping after `limit` solutions have been found.  If
+/// `keep` is false, the solutions are only counted.
+pub fn search<V: CandVec>(ken: &KenKen, limit: Option<usize>, keep: bool,
+                          strategy: Strategy) -> Solutions {
+    search_with(ken, &Constraints::<V>::new(ken), limit, keep, strategy)
+}
+
+/// Like `search`, but starts from the given constraints, e.g. those reduced
+/// with other techniques (see `Constraints::with_techniques`).  The puzzle
+/// must be valid and covered by cages.
+pub fn search_with<'a, V: CandVec>(ken: &'a KenKen, cons: &Constraints<'a, V>,
+                                   limit: Option<usize>, keep: bool,
+                                   strategy: Strategy) -> Solutions {
+    run(ken, cons, limit, keep, strategy, u32::MAX)
+}
+
+/// Like `search`, but gives up after `max_steps` steps, which sets
+/// `limit_reached`.  The solutions are only counted.
+pub fn search_bounded<V: CandVec>(ken: &KenKen, limit: Option<usize>, strategy: Strategy,
+                                  max_steps: u32) -> Solutions {
+    run(ken, &Constraints::<V>::new(ken), limit, false, strategy, max_steps)
+}
+
+fn run<'a, V: CandVec>(ken: &'a KenKen, cons: &Constraints<'a, V>, limit: Option<usize>,
+                       keep: bool, strategy: Strategy, max_steps: u32) -> Solutions {
+    let mut search = Search {
+        ken,
+        cons,
+        strategy,
+        work: Tbl::square(ken.size, 0),
+        mask: HouseMask::new(ken.size, &ken.digit_set(), ken.houses()),
+        placed: vec![false; ken.cages.len()],
+        neighbors: if strategy == Strategy::MinRemaining { neighbors(ken) } else { Vec::new() },
+        steps: 0,
+        max_steps,
+        count: 0,
+        limit: limit.unwrap_or(usize::MAX),
+        keep,
+        solutions: Vec::new(),
+    };
+    let limit_reached = search.limit > 0 && search.inner(0);
+    Solutions { steps: search.steps, count: search.count, solutions: search.solutions,
+                limit_reached }
+}
+
+/// Returns the cells (row, col) that do not have the same number in all
+/// given solutions.
+pub fn differing_cells<T: PartialEq>(solutions: &[Tbl<T>]) -> Vec<(usize, usize)> {
+    let mut res = Vec::new();
+    if let Some(first) = solutions.first() {
+        for row in 0..first.size() {
+            for col in 0..first.size() {
+                if solutions.iter().any(|sol| sol.get(row, col) != first.get(row, col)) {
+                    res.push((row, col));
+                }
+            }
+        }
+    }
+    res
+}
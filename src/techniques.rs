@@ -0,0 +1,394 @@
+// KenKen puzzle solver, (c) 2016 Georg Brandl.
+
+//! Deduction techniques that reduce the candidates in `Constraints`.
+//!
+//! Each technique implements the `Technique` trait.  A `Techniques` pipeline
+//! holds the registered techniques in the order they are applied; each one
+//! can be enabled or disabled, and the pipeline keeps statistics of how much
+//! every technique contributed.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use constraints::Constraints;
+use helpers::{CandVec, SmallVec};
+use rating::Grade;
+
+/// A deduction technique.
+pub trait Technique<V: CandVec> {
+    /// Returns the name of the technique, e.g. `naked-singles`.
+    fn name(&self) -> &'static str;
+
+    /// Returns the grade that a rating gives at least if this technique is
+    /// needed.
+    fn grade(&self) -> Grade;
+
+    /// Returns how much each round in which this technique is needed adds to
+    /// the difficulty score of a rating.
+    fn weight(&self) -> u32;
+
+    /// Applies the technique once to all houses or cages.
+    ///
+    /// Returns true if anything was changed.
+    fn apply(&self, cons: &mut Constraints<V>) -> bool;
+}
+
+/// Removes known numbers from the other cells of their houses
+/// (see `Constraints::reduce_singles`).
+pub struct NakedSingles;
+
+impl<V: CandVec> Technique<V> for NakedSingles {
+    fn name(&self) -> &'static str {
+        "naked-singles"
+    }
+
+    fn grade(&self) -> Grade {
+        Grade::Easy
+    }
+
+    fn weight(&self) -> u32 {
+        1
+    }
+
+    fn apply(&self, cons: &mut Constraints<V>) -> bool {
+        cons.reduce_singles()
+    }
+}
+
+/// Removes the numbers of two cells with the same two candidates from the
+/// other cells of their houses (see `Constraints::reduce_pairs`).
+pub struct NakedPairs;
+
+impl<V: CandVec> Technique<V> for NakedPairs {
+    fn name(&self) -> &'static str {
+        "naked-pairs"
+    }
+
+    fn grade(&self) -> Grade {
+        Grade::Medium
+    }
+
+    fn weight(&self) -> u32 {
+        5
+    }
+
+    fn apply(&self, cons: &mut Constraints<V>) -> bool {
+        cons.reduce_pairs()
+    }
+}
+
+/// Fixes cells that are the only place for a number in one of their houses
+/// (see `Constraints::reduce_hidden_singles`).
+pub struct HiddenSingles;
+
+impl<V: CandVec> Technique<V> for HiddenSingles {
+    fn name(&self) -> &'static str {
+        "hidden-singles"
+    }
+
+    fn grade(&self) -> Grade {
+        Grade::Hard
+    }
+
+    fn weight(&self) -> u32 {
+        10
+    }
+
+    fn apply(&self, cons: &mut Constraints<V>) -> bool {
+        cons.reduce_hidden_singles()
+    }
+}
+
+/// Removes a number from the rest of a house if every combination of a cage
+/// puts it into the cage's cells in that house (see
+/// `Constraints::reduce_cage_houses`).
+pub struct CageHouses;
+
+impl<V: CandVec> Technique<V> for CageHouses {
+    fn name(&self) -> &'static str {
+        "cage-houses"
+    }
+
+    fn grade(&self) -> Grade {
+        Grade::Medium
+    }
+
+    fn weight(&self) -> u32 {
+        5
+    }
+
+    fn apply(&self, cons: &mut Constraints<V>) -> bool {
+        cons.reduce_cage_houses()
+    }
+}
+
+/// Removes numbers that don't fit the known sum or product of the cells of
+/// a few rows or columns left over by the Add and Mul cages within (see
+/// `Constraints::reduce_innies_outies`).
+pub struct InniesOuties;
+
+impl<V: CandVec> Technique<V> for InniesOuties {
+    fn name(&self) -> &'static str {
+        "innies-outies"
+    }
+
+    fn grade(&self) -> Grade {
+        Grade::Medium
+    }
+
+    fn weight(&self) -> u32 {
+        10
+    }
+
+    fn apply(&self, cons: &mut Constraints<V>) -> bool {
+        cons.reduce_innies_outies()
+    }
+}
+
+/// Removes other numbers from two cells that are the only places for two
+/// numbers in a house (see `Constraints::reduce_hidden_subsets`).
+pub struct HiddenPairs;
+
+impl<V: CandVec> Technique<V> for HiddenPairs {
+    fn name(&self) -> &'static str {
+        "hidden-pairs"
+    }
+
+    fn grade(&self) -> Grade {
+        Grade::Hard
+    }
+
+    fn weight(&self) -> u32 {
+        15
+    }
+
+    fn apply(&self, cons: &mut Constraints<V>) -> bool {
+        cons.reduce_hidden_subsets(2)
+    }
+}
+
+/// Removes other numbers from three cells that are the only places for
+/// three numbers in a house.
+pub struct HiddenTriples;
+
+impl<V: CandVec> Technique<V> for HiddenTriples {
+    fn name(&self) -> &'static str {
+        "hidden-triples"
+    }
+
+    fn grade(&self) -> Grade {
+        Grade::Hard
+    }
+
+    fn weight(&self) -> u32 {
+        15
+    }
+
+    fn apply(&self, cons: &mut Constraints<V>) -> bool {
+        cons.reduce_hidden_subsets(3)
+    }
+}
+
+/// Removes the numbers of three cells that can only contain these three
+/// numbers from the other cells of their house (see
+/// `Constraints::reduce_naked_subsets`).
+pub struct NakedTriples;
+
+impl<V: CandVec> Technique<V> for NakedTriples {
+    fn name(&self) -> &'static str {
+        "naked-triples"
+    }
+
+    fn grade(&self) -> Grade {
+        Grade::Hard
+    }
+
+    fn weight(&self) -> u32 {
+        15
+    }
+
+    fn apply(&self, cons: &mut Constraints<V>) -> bool {
+        cons.reduce_naked_subsets(3)
+    }
+}
+
+/// Removes the numbers of four cells that can only contain these four
+/// numbers from the other cells of their house.
+pub struct NakedQuads;
+
+impl<V: CandVec> Technique<V> for NakedQuads {
+    fn name(&self) -> &'static str {
+        "naked-quads"
+    }
+
+    fn grade(&self) -> Grade {
+        Grade::Hard
+    }
+
+    fn weight(&self) -> u32 {
+        15
+    }
+
+    fn apply(&self, cons: &mut Constraints<V>) -> bool {
+        cons.reduce_naked_subsets(4)
+    }
+}
+
+/// Statistics of a technique within a pipeline.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// Number of times the technique was applied.
+    pub calls: u32,
+    /// Number of times it changed something.
+    pub rounds: u32,
+    /// Number of candidates it eliminated, including those removed from
+    /// other cells of the same cages as a consequence.
+    pub eliminated: u32,
+    /// Total time spent in the technique.
+    pub time: Duration,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = self.time.as_secs() as f64 + 1e-9 * self.time.subsec_nanos() as f64;
+        write!(f, "{:5} rounds {:8} eliminated {:10.4} ms", self.rounds, self.eliminated,
+               time * 1000.)
+    }
+}
+
+/// A registered technique.
+struct Entry<V: CandVec> {
+    technique: Box<dyn Technique<V>>,
+    enabled: bool,
+    stats: Stats,
+}
+
+/// An ordered list of techniques, as used by `Constraints::reduce`.
+pub struct Techniques<V: CandVec = SmallVec> {
+    entries: Vec<Entry<V>>,
+}
+
+impl<V: CandVec> Default for Techniques<V> {
+    fn default() -> Techniques<V> {
+        Techniques { entries: Vec::new() }
+    }
+}
+
+impl<V: CandVec> Techniques<V> {
+    /// Creates an empty pipeline.
+    pub fn new() -> Techniques<V> {
+        Techniques::default()
+    }
+
+    /// Creates the pipeline used by default, with all built-in techniques
+    /// sorted by the grade they give in a rating: naked singles (easy), naked
+    /// pairs, cage/house interactions, innies and outies (medium), hidden
+    /// singles, hidden pairs, naked triples, hidden triples and naked quads
+    /// (hard).
+    pub fn standard() -> Techniques<V> {
+        let mut techniques = Techniques::new();
+        techniques.register(Box::new(NakedSingles));
+        techniques.register(Box::new(NakedPairs));
+        techniques.register(Box::new(CageHouses));
+        techniques.register(Box::new(InniesOuties));
+        techniques.register(Box::new(HiddenSingles));
+        techniques.register(Box::new(HiddenPairs));
+        techniques.register(Box::new(NakedTriples));
+        techniques.register(Box::new(HiddenTriples));
+        techniques.register(Box::new(NakedQuads));
+        techniques
+    }
+
+    /// Adds a technique at the end of the pipeline, enabled.
+    pub fn register(&mut self, technique: Box<dyn Technique<V>>) {
+        self.entries.push(Entry { technique, enabled: true, stats: Stats::default() });
+    }
+
+    /// Returns the names of all registered techniques, in order.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.entries.iter().map(|entry| entry.technique.name()).collect()
+    }
+
+    /// Returns true if the technique with the given name is registered and
+    /// enabled.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.entries.iter().any(|entry| entry.enabled && entry.technique.name() == name)
+    }
+
+    /// Enables or disables the technique with the given name.  Returns false
+    /// if there is no such technique.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.technique.name() == name) {
+            Some(entry) => { entry.enabled = enabled; true }
+            None => false,
+        }
+    }
+
+    /// Enables exactly the given techniques, in the given order.  The other
+    /// ones are disabled and moved after them.  Returns the first unknown
+    /// name as an error.
+    pub fn select(&mut self, names: &[&str]) -> Result<(), String> {
+        let mut selected = Vec::with_capacity(self.entries.len());
+        for &name in names {
+            match self.entries.iter().position(|entry| entry.technique.name() == name) {
+                Some(i) => selected.push(self.entries.remove(i)),
+                None => return Err(name.into()),
+            }
+        }
+        for entry in &mut selected {
+            entry.enabled = true;
+        }
+        for entry in &mut self.entries {
+            entry.enabled = false;
+        }
+        selected.append(&mut self.entries);
+        self.entries = selected;
+        Ok(())
+    }
+
+    /// Returns the statistics of all registered techniques, in order.
+    pub fn stats(&self) -> Vec<(&'static str, Stats)> {
+        self.entries.iter().map(|entry| (entry.technique.name(), entry.stats)).collect()
+    }
+
+    /// Returns all registered techniques with their statistics, in order.
+    pub fn iter(&self) -> impl Iterator<Item=(&dyn Technique<V>, Stats)> {
+        self.entries.iter().map(|entry| (&*entry.technique, entry.stats))
+    }
+
+    /// Applies one technique, updating its statistics.
+    fn apply(entry: &mut Entry<V>, cons: &mut Constraints<V>) -> bool {
+        let start = Instant::now();
+        let before = cons.eliminated();
+        let changed = entry.technique.apply(cons);
+        entry.stats.calls += 1;
+        if changed {
+            entry.stats.rounds += 1;
+        }
+        entry.stats.eliminated += cons.eliminated() - before;
+        entry.stats.time += start.elapsed();
+        changed
+    }
+
+    /// Applies all enabled techniques once, in order.
+    ///
+    /// Returns true if anything was changed.
+    pub fn round(&mut self, cons: &mut Constraints<V>) -> bool {
+        let mut changed = false;
+        for entry in self.entries.iter_mut().filter(|entry| entry.enabled) {
+            changed |= Techniques::apply(entry, cons);
+        }
+        changed
+    }
+
+    /// Applies the enabled techniques in order until one of them changes
+    /// something, and returns its name.
+    pub fn step(&mut self, cons: &mut Constraints<V>) -> Option<&'static str> {
+        for entry in self.entries.iter_mut().filter(|entry| entry.enabled) {
+            if Techniques::apply(entry, cons) {
+                return Some(entry.technique.name());
+            }
+        }
+        None
+    }
+}